@@ -0,0 +1,104 @@
+use core::fmt::Display;
+use std::io::Write;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::compiler::Program;
+use crate::engine::Engine;
+use crate::tape::Tape;
+use crate::vm::RuntimeError;
+
+/// output closer together than this (in seconds) is merged into one event
+const MERGE_WINDOW: f64 = 0.01;
+
+/// Recording of the output of a run in the asciinema v2 format
+/// every event holds the seconds since the start and the text printed at that point
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cast {
+    pub width: u16,
+    pub height: u16,
+    pub timestamp: u64,
+    pub title: String,
+    pub events: Vec<(f64, String)>,
+}
+
+impl Cast {
+    /// run the program, print its output as usual and record it with timing
+    /// the cast is returned even if the run failed, it then ends at the error
+    pub fn record<T: Tape>(engine: &mut dyn Engine<T>, program: &Program, title: &str) -> (Cast, Result<(), RuntimeError>) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+        let mut cast = Cast { width: 80, height: 24, timestamp, title: title.to_string(), events: Vec::new() };
+        let started = Instant::now();
+        engine.load(program);
+        engine.machine().capture_output();
+
+        let result = loop {
+            let next = engine.step();
+            let output = engine.machine().take_output();
+            if !output.is_empty() {
+                cast.push(started.elapsed().as_secs_f64(), &output);
+            }
+            match next {
+                Ok(true) => {},
+                Ok(false) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+        (cast, result)
+    }
+
+    fn push(&mut self, time: f64, output: &[u8]) {
+        let mut stdout = std::io::stdout();
+        let mut text = String::new();
+        for &byte in output {
+            // the terminal translates newlines to carriage return and line feed, the player expects them already translated
+            if byte == b'\n' { text.push('\r'); }
+            // bytes are printed as the char with the same code, like the machine does
+            let ch = char::from(byte);
+            text.push(ch);
+            print!("{ch}");
+        }
+        let _ = stdout.flush();
+
+        match self.events.last_mut() {
+            Some((last, data)) if time - *last < MERGE_WINDOW => data.push_str(&text),
+            _ => self.events.push((time, text)),
+        }
+    }
+}
+
+impl Display for Cast {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}, \"title\": {}}}",
+            self.width, self.height, self.timestamp, json_string(&self.title)
+        )?;
+        for (time, data) in &self.events {
+            writeln!(f, "[{time:.6}, \"o\", {}]", json_string(data))?;
+        }
+        Ok(())
+    }
+}
+
+/// quoted json string, non-ascii characters are escaped as well
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            ' '..='~' => json.push(ch),
+            _ => {
+                let mut units = [0; 2];
+                for unit in ch.encode_utf16(&mut units) {
+                    json.push_str(&format!("\\u{unit:04x}"));
+                }
+            },
+        }
+    }
+    json.push('"');
+    json
+}
@@ -1,19 +1,358 @@
+use clap::ValueEnum;
+use core::fmt::Display;
 use core::ops::Deref;
+use std::collections::{BTreeMap, HashSet};
 use std::collections::hash_map::HashMap;
+use std::str::CharIndices;
+use unicode_width::UnicodeWidthChar;
+
+use crate::diagnostic::{Diagnostic, ErrorStyle};
+use crate::stats::{CompileStats, Histogram};
+use crate::vm::OverflowPolicy;
+
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// Optional language extensions, that add commands on top of the eight standard ones
+#[derive(Debug, PartialEq, Eq, Clone, Copy, ValueEnum)]
+pub enum Extension {
+    /// `}` and `{` switch to the next/previous of several independent tapes
+    MultiTape,
+    /// `?` stores a random byte in the current cell
+    Rand,
+}
+
+/// Options that change how a program is compiled
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    pub optimize: bool,
+    pub extensions: Vec<Extension>,
+    /// width of a tab, used for the columns in error messages
+    pub tab_width: usize,
+    /// deepest allowed loop nesting, parsing stops with an error once it is exceeded
+    pub max_depth: Option<usize>,
+    /// overflow policy of the machine, that runs the program
+    /// with `OverflowPolicy::Error` the passes, that rely on wrapping cells, are skipped
+    pub overflow: OverflowPolicy,
+    /// optimizer passes to run, if `optimize` is set
+    pub passes: Vec<Pass>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions { optimize: false, extensions: Vec::new(), tab_width: DEFAULT_TAB_WIDTH, max_depth: None, overflow: OverflowPolicy::Wrap, passes: Pass::ALL.to_vec() }
+    }
+}
+
+/// Optimizer passes, in the order they run
+#[derive(Debug, PartialEq, Eq, Clone, Copy, ValueEnum)]
+pub enum Pass {
+    /// replace loops, that add multiples of their counter to other cells, with `MulAdd`
+    MulAdd,
+    /// fold the arithmetic on known cells into `Set` and their output into `PutStr`
+    ConstantPropagation,
+    /// reorder the cell updates of straight-line code, so the pointer moves less
+    PointerMoves,
+    /// remove writes, that are overwritten before they are read
+    DeadStores,
+    /// batch runs of `.` and `,` into `PutN` and `GetN`
+    IoBatching,
+    /// turn loops, that run at most once, into `If`
+    RunOnceLoops,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 6] = [Pass::MulAdd, Pass::ConstantPropagation, Pass::PointerMoves, Pass::DeadStores, Pass::IoBatching, Pass::RunOnceLoops];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::MulAdd => "mul-add",
+            Pass::ConstantPropagation => "constant-propagation",
+            Pass::PointerMoves => "pointer-moves",
+            Pass::DeadStores => "dead-stores",
+            Pass::IoBatching => "io-batching",
+            Pass::RunOnceLoops => "run-once-loops",
+        }
+    }
+
+    /// true if the pass computes or drops cell updates as if cells wrap around at 0 and 255,
+    /// or reorders them, so with `OverflowPolicy::Error` an overflow could go unnoticed or another error come first
+    pub fn needs_wrapping(self) -> bool {
+        matches!(self, Pass::MulAdd | Pass::ConstantPropagation | Pass::PointerMoves | Pass::DeadStores)
+    }
+}
+
+impl CompileOptions {
+    /// true if `ch` is a command with the enabled extensions, every other char is a comment
+    pub fn is_command(&self, ch: char) -> bool {
+        match ch {
+            '+' | '-' | '<' | '>' | '[' | ']' | '.' | ',' => true,
+            '}' | '{' => self.extensions.contains(&Extension::MultiTape),
+            '?' => self.extensions.contains(&Extension::Rand),
+            _ => false,
+        }
+    }
+}
+
+/// Position of a character in the source
+/// `col` is the display column (tabs expanded, wide characters count double), `byte` the byte offset inside the line
+/// line and col start at 1, byte at 0
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub col: usize,
+    pub byte: usize,
+}
+
+impl Position {
+    /// display column following `ch`, if `ch` starts at `col`
+    pub fn advance_col(col: usize, ch: char, tab_width: usize) -> usize {
+        match ch {
+            '\t' if tab_width > 0 => (col - 1) / tab_width * tab_width + tab_width + 1,
+            _ => col + ch.width().unwrap_or(0),
+        }
+    }
+
+    /// position of the char at `char_index`
+    pub fn of_char(source: &str, char_index: usize, tab_width: usize) -> Position {
+        let mut pos = Position { line: 1, col: 1, byte: 0 };
+        for ch in source.chars().take(char_index) {
+            if ch == '\n' {
+                pos = Position { line: pos.line + 1, col: 1, byte: 0 };
+            } else {
+                pos.col = Position::advance_col(pos.col, ch, tab_width);
+                pos.byte += ch.len_utf8();
+            }
+        }
+        pos
+    }
+}
 
 #[derive(Debug)]
 enum Token {
-    RBrac { line: usize, col: usize },  // Brackets store position information, because they are the only Tokens, that can produce ParseErrors
-    LBrac { line: usize, col: usize },
-    Plus,
-    Minus,
-    Less,
-    Greater,
+    RBrac(Position),  // Brackets store position information, because they are the only Tokens, that can produce ParseErrors
+    LBrac(Position),
+    Plus(usize),  // Commands, that can be repeated, store how often
+    Minus(usize),
+    Less(usize),
+    Greater(usize),
     Dot,
     Comma,
+    RBrace(usize),
+    LBrace(usize),
+    Question,
     Eof,
 }
 
+/// Known cell values in a stretch of straight-line code, used by constant propagation
+/// offsets are relative to the pointer at the start of the stretch
+struct Segment {
+    /// `None` marks a cell, whose value is unknown
+    known: HashMap<isize, Option<u8>>,
+    /// if cells that aren't in `known` are zero
+    zeroed: bool,
+    /// writes that weren't emitted yet
+    pending: BTreeMap<isize, u8>,
+    /// where the pointer would be
+    offset: isize,
+    /// where the pointer is, with the instructions emitted so far
+    emitted: isize,
+    /// leftmost and rightmost cell the program reached
+    reached: (isize, isize),
+    /// cells, where the program got further left or right than before, that the emitted moves haven't reached yet
+    records: Vec<isize>,
+    /// leftmost and rightmost cell the emitted moves reached
+    visited: (isize, isize),
+}
+
+impl Segment {
+    fn new(zeroed: bool) -> Self {
+        Segment {
+            known: HashMap::new(),
+            zeroed,
+            pending: BTreeMap::new(),
+            offset: 0,
+            emitted: 0,
+            reached: (0, 0),
+            records: Vec::new(),
+            visited: (0, 0),
+        }
+    }
+
+    /// move the pointer like the program does, the move is only emitted when something needs it
+    fn shift(&mut self, by: isize) {
+        self.offset += by;
+        self.reach(self.offset);
+    }
+
+    /// the program reached the cell, which fails if it is past an end of the tape
+    fn reach(&mut self, offset: isize) {
+        if offset < self.reached.0 || offset > self.reached.1 {
+            self.reached = (self.reached.0.min(offset), self.reached.1.max(offset));
+            self.records.push(offset);
+        }
+    }
+
+    /// move through the cells the program reached, in the same order, before anything observable happens
+    /// so a move past an end of the tape fails at the same point and with the same error as without optimizing
+    fn check_reach(&mut self, instructions: &mut Vec<Instruction>) {
+        for offset in std::mem::take(&mut self.records) {
+            if offset < self.visited.0 || offset > self.visited.1 {
+                self.emit_move(offset, instructions);
+            }
+        }
+    }
+
+    fn value(&self) -> Option<u8> {
+        match self.known.get(&self.offset) {
+            Some(value) => *value,
+            None if self.zeroed => Some(0),
+            None => None,
+        }
+    }
+
+    fn write(&mut self, value: u8) {
+        self.known.insert(self.offset, Some(value));
+        self.pending.insert(self.offset, value);
+    }
+
+    /// the current cell got a value, that can't be known at compile time
+    fn forget(&mut self) {
+        self.known.insert(self.offset, None);
+    }
+
+    fn move_to(&mut self, offset: isize, instructions: &mut Vec<Instruction>) {
+        self.check_reach(instructions);
+        self.emit_move(offset, instructions);
+    }
+
+    fn emit_move(&mut self, offset: isize, instructions: &mut Vec<Instruction>) {
+        emit_move(offset - self.emitted, instructions);
+        self.emitted = offset;
+        self.visited = (self.visited.0.min(offset), self.visited.1.max(offset));
+    }
+
+    fn move_to_offset(&mut self, instructions: &mut Vec<Instruction>) {
+        self.move_to(self.offset, instructions);
+    }
+
+    /// apply a `MulAdd` at compile time, if the counter and all cells it writes are known
+    fn mul_add(&mut self, updates: &[(isize, u8)]) -> bool {
+        let Some(count) = self.value() else { return false; };
+        let base = self.offset;
+        let mut values = Vec::with_capacity(updates.len());
+        for (offset, factor) in updates {
+            self.offset = base + offset;
+            let Some(value) = self.value() else {
+                self.offset = base;
+                return false;
+            };
+            values.push((self.offset, value.wrapping_add(count.wrapping_mul(*factor))));
+        }
+        for (offset, value) in values {
+            // the loop only visits the cells, if it runs
+            if count != 0 {
+                self.reach(offset);
+            }
+            self.offset = offset;
+            self.write(value);
+        }
+        self.offset = base;
+        self.write(0);
+        true
+    }
+
+    /// emit all pending writes and move the pointer to where it should be
+    fn flush(&mut self, instructions: &mut Vec<Instruction>) {
+        for (offset, value) in std::mem::take(&mut self.pending) {
+            self.move_to(offset, instructions);
+            instructions.push(Instruction::Set(value));
+        }
+        self.move_to_offset(instructions);
+    }
+}
+
+/// Streaming tokenizer over a bf program
+/// extension commands are only recognized if the extension is enabled, otherwise they are comments
+/// bracket positions are tracked both as display column and byte offset
+/// when optimizing, runs of the same command (even with comments in between) become a single counted token
+struct Tokens<'a> {
+    chars: CharIndices<'a>,
+    options: &'a CompileOptions,
+    line: usize,
+    col: usize,
+    line_start: usize,
+    done: bool,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(program: &'a str, options: &'a CompileOptions) -> Self {
+        Tokens { chars: program.char_indices(), options, line: 1, col: 1, line_start: 0, done: false }
+    }
+
+    /// position of the char and move past it
+    fn advance(&mut self, byte: usize, char: char) -> Position {
+        let pos = Position { line: self.line, col: self.col, byte: byte - self.line_start };
+        self.col = Position::advance_col(self.col, char, self.options.tab_width);
+        if char == '\n' {
+            self.line += 1;
+            self.col = 1;
+            self.line_start = byte + 1;
+        }
+        pos
+    }
+
+    /// count how often `command` repeats, the first one is already consumed
+    fn run(&mut self, command: char) -> usize {
+        if !self.options.optimize { return 1; }
+
+        let mut count = 1;
+        while let Some((byte, char)) = self.chars.clone().next() {
+            if char == command {
+                count += 1;
+            } else if self.options.is_command(char) {
+                break;
+            }
+            self.chars.next();
+            self.advance(byte, char);
+        }
+        count
+    }
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = (Position, Token);
+
+    fn next(&mut self) -> Option<(Position, Token)> {
+        while let Some((byte, char)) = self.chars.next() {
+            let pos = self.advance(byte, char);
+            if !self.options.is_command(char) { continue; }
+
+            let token = match char {
+                '+' => Token::Plus(self.run(char)),
+                '-' => Token::Minus(self.run(char)),
+                '<' => Token::Less(self.run(char)),
+                '>' => Token::Greater(self.run(char)),
+                ']' => Token::RBrac(pos),
+                '[' => Token::LBrac(pos),
+                '.' => Token::Dot,
+                ',' => Token::Comma,
+                '}' => Token::RBrace(self.run(char)),
+                '{' => Token::LBrace(self.run(char)),
+                _ => Token::Question,
+            };
+            return Some((pos, token));
+        }
+
+        if self.done {
+            None
+        } else {
+            self.done = true;
+            let end = Position { line: self.line, col: self.col, byte: self.chars.offset() - self.line_start };
+            Some((end, Token::Eof))
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Instruction {
     MvLeft(usize),
@@ -22,79 +361,364 @@ pub enum Instruction {
     Dec(usize),
     Jmp(usize),
     JmpZ(usize),
+    /// a loop, that runs at most once: skip to the address if the cell is zero, there is no jump back
+    If(usize),
     Get,
     Put,
+    /// print bytes, that were known at compile time, produced by constant propagation
+    PutStr(Vec<u8>),
+    /// print `count` cells, `stride` cells apart, starting at the current one, the pointer ends on the last
+    PutN { count: usize, stride: isize },
+    /// read into `count` cells, `stride` cells apart, starting at the current one, the pointer ends on the last
+    GetN { count: usize, stride: isize },
+    NextTape(usize),
+    PrevTape(usize),
+    Rand,
+    /// store a constant in the current cell, produced by constant propagation
+    Set(u8),
+    /// a loop, that decrements its counter by one and only adds constants to other cells:
+    /// add the current cell times the factor to the cell at every offset (wrapping), then clear the current cell
+    /// only produced for wrapping cells, with `OverflowPolicy::Error` the loop is kept, so every step is checked
+    MulAdd(Vec<(isize, u8)>),
     Exit,
 }
 
 impl Instruction {
-    fn increment(&mut self) -> bool {
+    /// true if executing the instruction modifies the current cell
+    pub fn writes_cell(&self) -> bool {
+        matches!(self, Instruction::Inc(_) | Instruction::Dec(_) | Instruction::Get | Instruction::GetN { .. } | Instruction::Rand | Instruction::Set(_) | Instruction::MulAdd(_))
+    }
+
+    /// name of the instruction without its operand
+    pub fn name(&self) -> &'static str {
         match self {
-            Instruction::MvLeft(amount) => *amount += 1,
-            Instruction::MvRight(amount) => *amount += 1,
-            Instruction::Inc(amount) => *amount += 1,
-            Instruction::Dec(amount) => *amount += 1,
-            _ => return false,
+            Instruction::MvLeft(_) => "MvLeft",
+            Instruction::MvRight(_) => "MvRight",
+            Instruction::Inc(_) => "Inc",
+            Instruction::Dec(_) => "Dec",
+            Instruction::Jmp(_) => "Jmp",
+            Instruction::JmpZ(_) => "JmpZ",
+            Instruction::If(_) => "If",
+            Instruction::Get => "Get",
+            Instruction::Put => "Put",
+            Instruction::PutStr(_) => "PutStr",
+            Instruction::PutN { .. } => "PutN",
+            Instruction::GetN { .. } => "GetN",
+            Instruction::NextTape(_) => "NextTape",
+            Instruction::PrevTape(_) => "PrevTape",
+            Instruction::Rand => "Rand",
+            Instruction::Set(_) => "Set",
+            Instruction::MulAdd(_) => "MulAdd",
+            Instruction::Exit => "Exit",
         }
-        true
     }
 }
 
+#[derive(Debug)]
 pub struct ParseError {
     errors: Vec<Token>,
+    pairs: Vec<(Position, Position)>,
+    /// the bracket that exceeded the nesting limit, and the limit
+    too_deep: Option<(Position, usize)>,
+    /// the optimizer pass, that left the program broken, and the broken invariant
+    invalid: Option<Box<(Pass, ValidationError)>>,
+    tab_width: usize,
 }
 
 impl ParseError {
-    fn new() -> Self {
-        ParseError { errors: Vec::new() }
+    fn new(tab_width: usize) -> Self {
+        ParseError { errors: Vec::new(), pairs: Vec::new(), too_deep: None, invalid: None, tab_width }
+    }
+
+    fn invalid(tab_width: usize, pass: Pass, err: ValidationError) -> Self {
+        ParseError { invalid: Some(Box::new((pass, err))), ..ParseError::new(tab_width) }
+    }
+
+    /// the optimizer pass, that broke the program, and how, if that is why compiling failed
+    pub fn validation(&self) -> Option<(Pass, &ValidationError)> {
+        self.invalid.as_deref().map(|(pass, err)| (*pass, err))
     }
 
     fn report_error(&mut self, token: Token) {
         self.errors.push(token)
     }
 
+    /// remember matched brackets, to find the likely culprit of an unmatched one
+    fn report_pair(&mut self, open: Position, close: Position) {
+        self.pairs.push((open, close))
+    }
+
+    fn report_too_deep(&mut self, pos: Position, limit: usize) {
+        self.too_deep = Some((pos, limit))
+    }
+
     fn had_error(&self) -> bool {
-        !self.errors.is_empty()
+        self.error_count() > 0
     }
 
-    fn format_error(line: usize, col: usize, line_str: &str) -> String {
-        let mut error_str = format!("{line} {line_str}");
-        let ln_len = line.to_string().len();
-        let arrow = col + ln_len;
-        error_str.push_str("\n ");
-        error_str.push_str(&" ".repeat(arrow));
-        error_str.push('^');
-        error_str
+    fn error_count(&self) -> usize {
+        self.errors.len() + usize::from(self.too_deep.is_some()) + usize::from(self.invalid.is_some())
     }
 
-    pub fn get_error_msg(mut self, program: &str) -> String {
-        let ending = if self.errors.len() == 1 { '\0' } else { 's' };
-        let mut msg = format!("{} error{} occured during parsing:\n", self.errors.len(), ending);
+    /// structured diagnostics for all errors, with suggestions where the nesting points to a culprit
+    pub fn diagnostics(&self, program: &str) -> Vec<Diagnostic> {
+        let indents = line_indents(program, self.tab_width);
+        let mut suspects = Vec::new();
+        self.errors.iter().rev()
+            .map(|err| match err {
+                Token::RBrac(pos) => self.suggest_open(ParseError::diagnostic(err), *pos, &indents, &mut suspects),
+                Token::LBrac(pos) => self.suggest_close(ParseError::diagnostic(err), *pos, &indents, &mut suspects),
+                _ => ParseError::diagnostic(err),
+            })
+            .chain(self.too_deep_diagnostic())
+            .chain(self.invalid_diagnostic())
+            .collect()
+    }
 
-        self.errors.reverse();
-        for err in self.errors {
-            let str = match err {
-                Token::RBrac { line, col } => {
-                    let line_str = program.lines().nth(line-1).expect("line should always exist");
-                    format!("Unexpected closing bracket found at {line}:{col}: \n {}\n", ParseError::format_error(line, col, line_str))
-                },
-                Token::LBrac { line, col } => {
-                    let line_str = program.lines().nth(line-1).expect("line should always exist");
-                    format!("Opening bracket at {line}:{col} wasn't closed: \n {}\n", ParseError::format_error(line, col, line_str))
-                },
-                _ => format!("Unexpected Error at {:?}\n", err),
-            };
-            msg.push_str(&str);
+    /// the error for a single token, without suggestions
+    fn diagnostic(err: &Token) -> Diagnostic {
+        match err {
+            Token::RBrac(pos) => Diagnostic::error(*pos, String::from("Unexpected closing bracket found")),
+            Token::LBrac(pos) => Diagnostic::error(*pos, String::from("Unclosed opening bracket")),
+            _ => Diagnostic::error(Position { line: 1, col: 1, byte: 0 }, format!("Unexpected Error at {:?}", err)),
+        }
+    }
+
+    fn too_deep_diagnostic(&self) -> Option<Diagnostic> {
+        self.too_deep.map(|(pos, limit)| {
+            Diagnostic::error(pos, format!("Loop nesting exceeds the maximum depth of {limit}"))
+                .with_help(String::from("the limit is set with --max-depth"))
+        })
+    }
+
+    fn invalid_diagnostic(&self) -> Option<Diagnostic> {
+        self.invalid.as_deref().map(|(pass, err)| {
+            Diagnostic::error(Position { line: 1, col: 1, byte: 0 }, format!("Optimizer pass {} broke the program: {err}", pass.name()))
+                .with_help(String::from("this is a bug in the optimizer, the program still runs without --optimize"))
+        })
+    }
+
+    /// pairs whose brackets are on different lines with a different indentation
+    /// these are likely to have taken the bracket of an outer loop
+    fn suspicious_pairs<'a>(&'a self, indents: &'a [Option<usize>], suspects: &'a [Position]) -> impl Iterator<Item = &'a (Position, Position)> {
+        self.pairs.iter()
+            .filter(move |(open, close)| open.line != close.line && indents[open.line - 1] != indents[close.line - 1])
+            .filter(move |(open, _)| !suspects.contains(open))
+    }
+
+    /// an unclosed `[` is often not the bracket that misses its `]`, but a later one, that took the `]` of the outer loop
+    fn suggest_close(&self, diagnostic: Diagnostic, unclosed: Position, indents: &[Option<usize>], suspects: &mut Vec<Position>) -> Diagnostic {
+        let Some(&(open, close)) = self.suspicious_pairs(indents, suspects)
+            .filter(|(open, _)| *open > unclosed)
+            .min_by_key(|(open, _)| *open) else { return diagnostic; };
+        suspects.push(open);
+
+        // the loop probably ends before the next line, that isn't indented deeper than the `[`
+        let end = (open.line + 1..close.line)
+            .find(|line| indents[line - 1].is_some_and(|ind| Some(ind) <= indents[open.line - 1]))
+            .unwrap_or(close.line);
+
+        diagnostic
+            .with_help(format!("this `[` at {}:{} is probably missing a `]` before line {}", open.line, open.col, end))
+            .with_label(open, "this `[`")
+            .with_label(line_start(end, indents), "missing `]` before this line")
+    }
+
+    /// an unexpected `]` is often caused by a missing `[` before it, so that an earlier `]` was matched to the outer loop
+    fn suggest_open(&self, diagnostic: Diagnostic, unexpected: Position, indents: &[Option<usize>], suspects: &mut Vec<Position>) -> Diagnostic {
+        let Some(&(open, close)) = self.suspicious_pairs(indents, suspects)
+            .filter(|(_, close)| *close < unexpected)
+            .max_by_key(|(_, close)| *close) else { return diagnostic; };
+        suspects.push(open);
+
+        // the loop probably starts at the closest line above, that isn't indented deeper than the `]`
+        let start = (open.line + 1..close.line).rev()
+            .find(|line| indents[line - 1].is_some_and(|ind| Some(ind) <= indents[close.line - 1]))
+            .unwrap_or(open.line + 1);
+
+        diagnostic
+            .with_help(format!("this `]` at {}:{} is probably missing its `[` in line {}", close.line, close.col, start))
+            .with_label(close, "this `]`")
+            .with_label(line_start(start, indents), "missing `[` in this line")
+    }
+
+    pub fn get_error_msg(self, program: &str) -> String {
+        self.render(ErrorStyle::Full, "", program)
+    }
+
+    /// render all errors in the given style, `file` names the program in `ErrorStyle::Short`
+    pub fn render(&self, style: ErrorStyle, file: &str, program: &str) -> String {
+        let mut msg = String::new();
+        if style != ErrorStyle::Short {
+            let ending = if self.error_count() == 1 { '\0' } else { 's' };
+            msg.push_str(&format!("{} error{} occured during parsing:\n", self.error_count(), ending));
+        }
+
+        for diagnostic in self.diagnostics(program) {
+            msg.push_str(&diagnostic.render_style(style, file, program, self.tab_width));
         }
 
         msg
     }
 }
 
+/// one `line:col: message` line per error, without the source
+/// use `render` or `get_error_msg` to show the offending lines as well
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let diagnostics = self.errors.iter().rev().map(ParseError::diagnostic).chain(self.too_deep_diagnostic()).chain(self.invalid_diagnostic());
+        for (index, diagnostic) in diagnostics.enumerate() {
+            if index > 0 { writeln!(f)?; }
+            write!(f, "{}:{}: {}", diagnostic.pos.line, diagnostic.pos.col, diagnostic.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// display width of the leading whitespace of every line, `None` for blank lines
+fn line_indents(program: &str, tab_width: usize) -> Vec<Option<usize>> {
+    program.lines()
+        .map(|line| {
+            let content = line.trim_start();
+            if content.is_empty() { return None; }
+            let indent = &line[..line.len() - content.len()];
+            Some(indent.chars().fold(1, |col, ch| Position::advance_col(col, ch, tab_width)) - 1)
+        })
+        .collect()
+}
+
+/// position of the first non-whitespace char of a line
+fn line_start(line: usize, indents: &[Option<usize>]) -> Position {
+    let col = indents.get(line - 1).copied().flatten().unwrap_or(0) + 1;
+    Position { line, col, byte: col - 1 }
+}
+
+/// A broken invariant of an instruction stream, found by `Program::validate`
+/// addresses are indices into the instructions
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ValidationError {
+    /// the last instruction isn't `Exit`
+    MissingExit,
+    /// the instruction at `at` jumps to `target`, which is past the end
+    JumpOutOfRange { at: usize, target: usize },
+    /// the `JmpZ` at `at` doesn't point at a `Jmp` leading back to it
+    UnmatchedJmpZ { at: usize },
+    /// the `Jmp` at `at` doesn't point at a `JmpZ` leading to it
+    UnmatchedJmp { at: usize },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::MissingExit => write!(f, "The program doesn't end with an Exit instruction"),
+            ValidationError::JumpOutOfRange { at, target } => write!(f, "Instruction {at} jumps to {target}, outside of the program"),
+            ValidationError::UnmatchedJmpZ { at } => write!(f, "The loop starting at {at} has no matching end"),
+            ValidationError::UnmatchedJmp { at } => write!(f, "The loop ending at {at} has no matching start"),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Check the invariants, that the machine relies on: the stream ends with `Exit`,
+/// every jump leads to an instruction and the `JmpZ` and `Jmp` of a loop point at each other
+pub fn validate_instructions(instructions: &[Instruction]) -> Result<(), ValidationError> {
+    if instructions.last() != Some(&Instruction::Exit) {
+        return Err(ValidationError::MissingExit);
+    }
+    for (at, instr) in instructions.iter().enumerate() {
+        let target = match instr {
+            Instruction::Jmp(addr) | Instruction::If(addr) => *addr,
+            // a `JmpZ` continues after its target
+            Instruction::JmpZ(addr) => addr.saturating_add(1),
+            _ => continue,
+        };
+        if target >= instructions.len() {
+            return Err(ValidationError::JumpOutOfRange { at, target });
+        }
+        match instr {
+            Instruction::JmpZ(end) if instructions[*end] != Instruction::Jmp(at) => return Err(ValidationError::UnmatchedJmpZ { at }),
+            Instruction::Jmp(start) if instructions[*start] != Instruction::JmpZ(at) => return Err(ValidationError::UnmatchedJmp { at }),
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// emit the updates of a straight-line block, sweeping left then right or right then left, whatever is shorter,
+/// and leave the pointer where the block left it, at the end of `path`, the cells the block moved to
+/// the sweep covers the whole path and only starts on the side the block reaches first, when both are possible,
+/// so a move past an end of the tape still fails with the same error; false if no sweep can keep that
+fn emit_block(mut updates: Vec<(isize, Instruction)>, path: &[isize], instructions: &mut Vec<Instruction>) -> bool {
+    let end = path.last().copied().unwrap_or(0);
+    let (min, max) = path.iter().fold((0, 0), |(min, max), &at| (min.min(at), max.max(at)));
+    let first = |found: &dyn Fn(isize) -> bool| path.iter().position(|&at| found(at));
+    let left_ok = min == 0 || max == 0 || first(&|at| at == min) < first(&|at| at > 0);
+    let right_ok = min == 0 || max == 0 || first(&|at| at == max) < first(&|at| at < 0);
+    if !left_ok && !right_ok {
+        return false;
+    }
+    let left_first = min.abs() + (max - min) + (end - max).abs();
+    let right_first = max.abs() + (max - min) + (end - min).abs();
+    let right_first = right_ok && (!left_ok || right_first < left_first);
+
+    // stable, so updates of the same cell stay in order
+    updates.sort_by_key(|(offset, _)| *offset);
+    if right_first {
+        // descending offsets, same cells still in their original order
+        updates.reverse();
+        let mut cells: Vec<Vec<(isize, Instruction)>> = Vec::new();
+        for update in updates {
+            match cells.last_mut() {
+                Some(cell) if cell[0].0 == update.0 => cell.push(update),
+                _ => cells.push(vec![update]),
+            }
+        }
+        updates = cells.into_iter().flat_map(|cell| cell.into_iter().rev()).collect();
+    }
+
+    let (near, far) = if right_first { (max, min) } else { (min, max) };
+    emit_move(near, instructions);
+    let mut at = near;
+    for (offset, instr) in updates {
+        emit_move(offset - at, instructions);
+        at = offset;
+        let merged = match (instructions.last_mut(), &instr) {
+            (Some(Instruction::Inc(times)), Instruction::Inc(more)) | (Some(Instruction::Dec(times)), Instruction::Dec(more)) => {
+                *times += more;
+                true
+            },
+            (Some(Instruction::Set(value)), Instruction::Set(new)) => {
+                *value = *new;
+                true
+            },
+            _ => false,
+        };
+        if !merged {
+            instructions.push(instr);
+        }
+    }
+    emit_move(far - at, instructions);
+    emit_move(end - far, instructions);
+    true
+}
+
+fn emit_move(by: isize, instructions: &mut Vec<Instruction>) {
+    if by > 0 {
+        instructions.push(Instruction::MvRight(by as usize));
+    } else if by < 0 {
+        instructions.push(Instruction::MvLeft(by.unsigned_abs()));
+    }
+}
+
 /// Wrapper for a Token vector to avoid manipulation
 #[derive(Debug)]
 pub struct Program {
     instructions: Vec<Instruction>,
+    /// source position of every instruction, empty once an optimizer pass moved instructions around
+    spans: Vec<Position>,
+    warnings: Vec<Diagnostic>,
+    stats: CompileStats,
 }
 
 impl Deref for Program {
@@ -106,52 +730,36 @@ impl Deref for Program {
 }
 
 impl Program {
-    /// parse a bf program to a series of Tokens
-    fn tokenize(program: &str) -> Vec<Token> {
-        let mut tokens = Vec::new();
-        let mut line = 1;
-        let mut col = 0;
-
-        for char in program.chars() {
-            col += 1;
-            let token = match char {
-                '+' => Token::Plus,
-                '-' => Token::Minus,
-                '<' => Token::Less,
-                '>' => Token::Greater,
-                ']' => Token::RBrac { line, col },
-                '[' => Token::LBrac { line, col },
-                '.' => Token::Dot,
-                ',' => Token::Comma,
-                '\n' => {
-                    line += 1;
-                    col = 0;
-                    continue;
-                },
-                _ => continue,
-            };
-            tokens.push(token);
-        }
-
-        tokens.push(Token::Eof);
-        tokens
-    }
-
-    fn parse(program: Vec<Token>) -> Result<Program, ParseError> {
+    /// turn the tokens into instructions, while they are produced
+    /// so the tokens of the whole program never have to be held in memory
+    fn parse(program: impl Iterator<Item = (Position, Token)>, options: &CompileOptions) -> Result<Program, ParseError> {
         let mut instructions = Vec::new();
+        let mut spans = Vec::new();
         let mut jmp_addresses = Vec::new();
-        let mut errors = ParseError::new();
+        let mut errors = ParseError::new(options.tab_width);
+        let mut warnings = Vec::new();
+        // depth of the outermost loop, that can never execute, warnings inside it would be redundant
+        let mut dead_loop = None;
 
-        for token in program {
+        for (pos, token) in program {
             let instr = match token {
-                Token::Plus => Instruction::Inc(1),
-                Token::Minus => Instruction::Dec(1),
-                Token::Greater => Instruction::MvRight(1),
-                Token::Less => Instruction::MvLeft(1),
+                Token::Plus(times) => Instruction::Inc(times),
+                Token::Minus(times) => Instruction::Dec(times),
+                Token::Greater(times) => Instruction::MvRight(times),
+                Token::Less(times) => Instruction::MvLeft(times),
                 Token::Dot => Instruction::Put,
                 Token::Comma => Instruction::Get,
-                Token::RBrac { .. } => {
+                Token::RBrace(times) => Instruction::NextTape(times),
+                Token::LBrace(times) => Instruction::PrevTape(times),
+                Token::Question => Instruction::Rand,
+                Token::RBrac(close) => {
                     if let Some((token, address)) = jmp_addresses.pop() {
+                        if let Token::LBrac(open) = token {
+                            errors.report_pair(open, close);
+                        }
+                        if dead_loop == Some(jmp_addresses.len()) {
+                            dead_loop = None;
+                        }
                         let jmp_addr = instructions.len();
                         match instructions.get_mut(address).expect("jmp address should always exist") {
                             Instruction::JmpZ(addr) => *addr = jmp_addr,
@@ -163,13 +771,24 @@ impl Program {
                         continue;
                     }
                 },
-                Token::LBrac { .. } => {
+                Token::LBrac(_) => {
+                    if options.max_depth.is_some_and(|limit| jmp_addresses.len() >= limit) {
+                        // stop right away, the rest of the program might only be built to exhaust memory
+                        errors.report_too_deep(pos, jmp_addresses.len());
+                        return Err(errors);
+                    }
+                    // the cell is zero at the start and after a loop, so a loop there is always skipped
+                    if dead_loop.is_none() && matches!(instructions.last(), None | Some(Instruction::Jmp(_))) {
+                        warnings.push(Diagnostic::warning(pos, String::from("Loop can never execute")));
+                        dead_loop = Some(jmp_addresses.len());
+                    }
                     jmp_addresses.push((token, instructions.len()));
                     Instruction::JmpZ(0)
                 }
                 Token::Eof => Instruction::Exit,
             };
-            instructions.push(instr)
+            instructions.push(instr);
+            spans.push(pos);
         }
 
         while let Some((token, _address)) = jmp_addresses.pop() {
@@ -179,55 +798,690 @@ impl Program {
         if errors.had_error() {
             Err(errors)
         } else {
-            Ok(Program { instructions })
+            if !instructions.contains(&Instruction::Put) {
+                warnings.push(Diagnostic::warning(Position { line: 1, col: 1, byte: 0 }, String::from("Program has no output instructions")));
+            }
+            let stats = CompileStats::new(&instructions);
+            Ok(Program { instructions, spans, warnings, stats })
         }
     }
 
+    /// Check the structural invariants of the instructions, see `validate_instructions`
+    /// a compiled program always passes, a failure points to a bug in an optimizer pass
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate_instructions(&self.instructions)
+    }
+
+    /// instruction counts after parsing and around every optimizer pass
+    pub fn stats(&self) -> &CompileStats {
+        &self.stats
+    }
+
+    /// position of the command in the source, that produced the instruction
+    /// `None` for the instructions of an optimized program, where they no longer match the source
+    pub fn span(&self, instr_ptr: usize) -> Option<Position> {
+        self.spans.get(instr_ptr).copied()
+    }
+
+    /// non-fatal diagnostics found while parsing
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
     pub fn from_str(program: &str, optimize: bool) -> Result<Program, ParseError> {
-        let mut program = Program::parse(Program::tokenize(program))?;
-        if optimize {
-            program.optimize();
+        Program::compile(program, &CompileOptions { optimize, ..Default::default() })
+    }
+
+    pub fn compile(program: &str, options: &CompileOptions) -> Result<Program, ParseError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("compile", bytes = program.len(), optimize = options.optimize).entered();
+
+        let mut program = {
+            // tokenizing is streamed into the parser, so both are covered by one span
+            #[cfg(feature = "tracing")]
+            let _span = tracing::info_span!("tokenize_parse").entered();
+            Program::parse(Tokens::new(program, options), options)?
+        };
+        if options.optimize {
+            program.optimize(true, options).map_err(|(pass, err)| ParseError::invalid(options.tab_width, pass, err))?;
         }
         Ok(program)
     }
 
-    fn optimize(&mut self) {
-        if self.instructions.is_empty() { return; }
+    /// Optimize the loop at `start` on its own, for engines that optimize a program while it runs
+    /// the loop is followed by an `Exit`, that is reached once the loop ends, and its jumps are relative to `start`
+    /// `None` if there is no loop at `start`, it holds instructions, that only the optimizer produces, or optimizing it failed
+    pub fn compile_loop(instructions: &[Instruction], start: usize, options: &CompileOptions) -> Option<Program> {
+        let Some(Instruction::JmpZ(end)) = instructions.get(start) else { return None; };
+        let body = instructions.get(start..=*end)?;
+        let parsed = body.iter().all(|instr| !matches!(instr,
+            Instruction::If(_) | Instruction::PutStr(_) | Instruction::PutN { .. } | Instruction::GetN { .. }
+            | Instruction::Set(_) | Instruction::MulAdd(_) | Instruction::Exit));
+        if !parsed {
+            return None;
+        }
+
+        let mut instructions: Vec<Instruction> = body.iter()
+            .map(|instr| match instr {
+                Instruction::Jmp(addr) => Instruction::Jmp(addr - start),
+                Instruction::JmpZ(addr) => Instruction::JmpZ(addr - start),
+                other => other.clone(),
+            })
+            .collect();
+        instructions.push(Instruction::Exit);
+        let stats = CompileStats::new(&instructions);
+        let mut program = Program { instructions, spans: Vec::new(), warnings: Vec::new(), stats };
+        // a loop, that the optimizer breaks, keeps running on the plain instructions
+        program.optimize(false, options).ok()?;
+        Some(program)
+    }
+
+    /// run the optimizer passes, that fit the options, `zeroed` if the program starts on a zeroed tape
+    /// the pass, that broke an invariant of the program, is reported with the invariant
+    fn optimize(&mut self, zeroed: bool, options: &CompileOptions) -> Result<(), (Pass, ValidationError)> {
+        for pass in Pass::ALL {
+            if !options.passes.contains(&pass) || (options.overflow == OverflowPolicy::Error && pass.needs_wrapping()) {
+                continue;
+            }
+            let run: fn(&mut Program) = match pass {
+                Pass::MulAdd => Program::fuse_mul_add,
+                Pass::ConstantPropagation if zeroed => Program::propagate_constants,
+                Pass::ConstantPropagation => Program::propagate_loop_constants,
+                Pass::PointerMoves => Program::minimize_moves,
+                Pass::DeadStores => Program::eliminate_dead_stores,
+                Pass::IoBatching => Program::batch_io,
+                Pass::RunOnceLoops => Program::lower_run_once_loops,
+            };
+            self.run_pass(pass.name(), run).map_err(|err| (pass, err))?;
+        }
+        Ok(())
+    }
 
+    /// run an optimizer pass and record the instructions before and after it
+    fn run_pass(&mut self, name: &'static str, pass: fn(&mut Program)) -> Result<(), ValidationError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("optimizer_pass", name, instructions = self.instructions.len()).entered();
+
+        let before = Histogram::of(&self.instructions);
+        pass(self);
+        self.validate()?;
+        self.spans.clear();
+        let after = Histogram::of(&self.instructions);
+        self.stats.record(name, before, after);
+        Ok(())
+    }
+
+    /// replace innermost loops, that only add and subtract constants and decrement their counter by one, with `MulAdd`
+    fn fuse_mul_add(&mut self) {
         let mut optimized_instructions = Vec::with_capacity(self.instructions.len());
-        let instr = self.instructions.first().expect("").clone();
-        let mut removed = 0usize;
-        let mut new_jmp_addrs = HashMap::new();
-        optimized_instructions.push(instr);
+        // new address of every kept instruction, the `Jmp`s and `JmpZ`s of fused loops are never targets
+        let mut new_addrs = vec![0; self.instructions.len()];
+        let mut i = 0;
+        while i < self.instructions.len() {
+            new_addrs[i] = optimized_instructions.len();
+            if let Some((end, updates)) = self.mul_add_updates(i) {
+                optimized_instructions.push(Instruction::MulAdd(updates));
+                i = end + 1;
+                continue;
+            }
+            optimized_instructions.push(self.instructions[i].clone());
+            i += 1;
+        }
 
-        for (i, instr) in self.instructions.iter().skip(1).enumerate() {
-            let last_added = optimized_instructions.last_mut().expect("vec shouldnt be empty");
+        for instr in &mut optimized_instructions {
+            if let Instruction::Jmp(addr) | Instruction::JmpZ(addr) = instr {
+                *addr = new_addrs[*addr];
+            }
+        }
+        self.instructions = optimized_instructions;
+    }
 
-            // increment count, if type is the same
-            if std::mem::discriminant(instr) == std::mem::discriminant(last_added) && last_added.increment() {
-                removed += 1; continue; 
+    /// the end and the updates of the loop at `start`, if it can be replaced by a `MulAdd`
+    /// the updates are in the order the body first reaches their cells, and the furthest cell on either side
+    /// has to be one of them, so the `MulAdd` runs past an end of the tape with the same error as the loop
+    fn mul_add_updates(&self, start: usize) -> Option<(usize, Vec<(isize, u8)>)> {
+        let Some(Instruction::JmpZ(end)) = self.instructions.get(start) else { return None; };
+        let mut offset = 0isize;
+        let mut factors: BTreeMap<isize, u8> = BTreeMap::new();
+        let mut reached = vec![0];
+        let (mut min, mut max) = (0, 0);
+        // the sides, on which the body got further than before, in order
+        let mut records: Vec<bool> = Vec::new();
+        for instr in &self.instructions[start + 1..*end] {
+            match instr {
+                Instruction::MvLeft(times) => offset -= *times as isize,
+                Instruction::MvRight(times) => offset += *times as isize,
+                Instruction::Inc(times) => {
+                    let factor = factors.entry(offset).or_insert(0);
+                    *factor = factor.wrapping_add((*times % 256) as u8);
+                },
+                Instruction::Dec(times) => {
+                    let factor = factors.entry(offset).or_insert(0);
+                    *factor = factor.wrapping_sub((*times % 256) as u8);
+                },
+                _ => return None,
             }
-            // save new jmp addresses if necessary
+            if !reached.contains(&offset) {
+                reached.push(offset);
+            }
+            if offset < min || offset > max {
+                records.push(offset > max);
+                (min, max) = (min.min(offset), max.max(offset));
+            }
+        }
+        if offset != 0 || factors.remove(&0) != Some(u8::MAX) {
+            return None;
+        }
+        // with the cells of one side checked first, a body that goes back and forth could fail on the wrong end
+        let updated = |at: isize| at == 0 || factors.get(&at).is_some_and(|factor| *factor != 0);
+        if !updated(min) || !updated(max) || records.windows(2).filter(|pair| pair[0] != pair[1]).count() > 1 {
+            return None;
+        }
+        let updates = reached.into_iter()
+            .filter_map(|at| factors.get(&at).filter(|factor| **factor != 0).map(|factor| (at, *factor)))
+            .collect();
+        Some((*end, updates))
+    }
+
+    /// fold arithmetic on cells with a known value into `Set` instructions
+    /// values are only known in straight-line code, starting with the zeroed tape and the zero cell after a loop
+    /// pointer moves inside such a stretch are combined, so the writes are only done when something observes the tape
+    fn propagate_constants(&mut self) {
+        self.propagate_from(Segment::new(true));
+    }
+
+    /// `propagate_constants` for a loop optimized on its own, where no cell is known at the start
+    fn propagate_loop_constants(&mut self) {
+        self.propagate_from(Segment::new(false));
+    }
+
+    fn propagate_from(&mut self, mut segment: Segment) {
+        let mut optimized_instructions = Vec::with_capacity(self.instructions.len());
+        let mut new_jmp_addrs = HashMap::new();
+
+        for (i, instr) in self.instructions.iter().enumerate() {
             match instr {
+                Instruction::MvLeft(times) => { segment.shift(-(*times as isize)); continue; },
+                Instruction::MvRight(times) => { segment.shift(*times as isize); continue; },
+                Instruction::Inc(times) | Instruction::Dec(times) => {
+                    let amount = (*times % 256) as u8;
+                    if let Some(value) = segment.value() {
+                        let value = if let Instruction::Inc(_) = instr { value.wrapping_add(amount) } else { value.wrapping_sub(amount) };
+                        segment.write(value);
+                        continue;
+                    }
+                    segment.move_to_offset(&mut optimized_instructions);
+                },
+                Instruction::Get | Instruction::Rand => {
+                    segment.flush(&mut optimized_instructions);
+                    segment.forget();
+                },
+                Instruction::Put => {
+                    // a known value doesn't need the cell, so the pending writes can stay pending
+                    if let Some(value) = segment.value() {
+                        segment.check_reach(&mut optimized_instructions);
+                        match optimized_instructions.last_mut() {
+                            Some(Instruction::PutStr(bytes)) => bytes.push(value),
+                            _ => optimized_instructions.push(Instruction::PutStr(vec![value])),
+                        }
+                        continue;
+                    }
+                    segment.flush(&mut optimized_instructions);
+                },
+                Instruction::PutStr(_) | Instruction::Exit | Instruction::Set(_) => segment.flush(&mut optimized_instructions),
                 Instruction::Jmp(_) | Instruction::JmpZ(_) => {
-                    new_jmp_addrs.insert(i + 1, removed);
+                    segment.flush(&mut optimized_instructions);
+                    new_jmp_addrs.insert(i, optimized_instructions.len());
                 },
-                _ => {},
-            };
+                Instruction::NextTape(_) | Instruction::PrevTape(_) => segment.flush(&mut optimized_instructions),
+                Instruction::MulAdd(updates) => {
+                    if segment.mul_add(updates) {
+                        continue;
+                    }
+                    segment.flush(&mut optimized_instructions);
+                    for (offset, _) in updates {
+                        segment.known.insert(segment.offset + offset, None);
+                    }
+                    segment.known.insert(segment.offset, Some(0));
+                },
+                Instruction::If(_) => unreachable!("ifs are only produced by the last pass"),
+                Instruction::PutN { .. } | Instruction::GetN { .. } => unreachable!("batched i/o is produced by a later pass"),
+            }
             optimized_instructions.push(instr.clone());
+
+            match instr {
+                // the body of a loop can be entered from its end, and other tapes have unknown values
+                Instruction::JmpZ(_) | Instruction::NextTape(_) | Instruction::PrevTape(_) => segment = Segment::new(false),
+                // a loop is only left, when the current cell is zero
+                Instruction::Jmp(_) => {
+                    segment = Segment::new(false);
+                    segment.known.insert(0, Some(0));
+                },
+                _ => {},
+            }
         }
 
         // patch jmp addresses
         for instr in &mut optimized_instructions {
+            if let Instruction::Jmp(addr) | Instruction::JmpZ(addr) = instr {
+                *addr = *new_jmp_addrs.get(addr).expect("jmp target should be kept");
+            }
+        }
+
+        optimized_instructions.shrink_to_fit();
+        self.instructions = optimized_instructions;
+    }
+
+    /// reorder the cell updates of straight-line code, so the pointer visits every cell once on the shortest path
+    /// updates of different cells commute, those of the same cell keep their order and runs of the same kind are merged
+    fn minimize_moves(&mut self) {
+        let mut optimized_instructions = Vec::with_capacity(self.instructions.len());
+        // jumps only lead to the start of a block, so every instruction of a block maps to its start
+        let mut new_addrs = Vec::with_capacity(self.instructions.len());
+        let mut i = 0;
+        while i < self.instructions.len() {
+            let block_start = i;
+            let mut offset = 0isize;
+            let mut path = Vec::new();
+            let mut updates: Vec<(isize, Instruction)> = Vec::new();
+            while let Some(instr) = self.instructions.get(i) {
+                match instr {
+                    Instruction::MvLeft(times) => { offset -= *times as isize; path.push(offset); },
+                    Instruction::MvRight(times) => { offset += *times as isize; path.push(offset); },
+                    Instruction::Inc(_) | Instruction::Dec(_) | Instruction::Set(_) => updates.push((offset, instr.clone())),
+                    _ => break,
+                }
+                i += 1;
+            }
+
+            new_addrs.extend(std::iter::repeat_n(optimized_instructions.len(), i - block_start));
+            if i == block_start {
+                new_addrs.push(optimized_instructions.len());
+                optimized_instructions.push(self.instructions[i].clone());
+                i += 1;
+                continue;
+            }
+            if !emit_block(updates, &path, &mut optimized_instructions) {
+                optimized_instructions.extend_from_slice(&self.instructions[block_start..i]);
+            }
+        }
+
+        for instr in &mut optimized_instructions {
+            if let Instruction::Jmp(addr) | Instruction::JmpZ(addr) = instr {
+                *addr = new_addrs[*addr];
+            }
+        }
+        self.instructions = optimized_instructions;
+    }
+
+    /// remove writes, whose value is overwritten before anything reads it, e.g. the clear in `,[-]+`
+    /// the cells are tracked backwards through straight-line code, where their offsets are known,
+    /// at a jump and at the end every cell counts as read, and `,` stays, since it consumes the input
+    fn eliminate_dead_stores(&mut self) {
+        let mut removed = vec![false; self.instructions.len()];
+        // cells, that are written before they are read, relative to the pointer after the instruction
+        let mut overwritten: HashSet<isize> = HashSet::new();
+        let mut offset = 0isize;
+        for (i, instr) in self.instructions.iter().enumerate().rev() {
             match instr {
-                Instruction::Jmp(addr) | Instruction::JmpZ(addr) => {
-                    *addr -= new_jmp_addrs.get(addr).expect("addr shoulb be in vec");
+                Instruction::MvLeft(times) => offset += *times as isize,
+                Instruction::MvRight(times) => offset -= *times as isize,
+                Instruction::Inc(_) | Instruction::Dec(_) => removed[i] = overwritten.contains(&offset),
+                Instruction::Set(_) => removed[i] = !overwritten.insert(offset),
+                Instruction::Rand => { overwritten.insert(offset); },
+                // with `--eof unchanged` a `,` can keep the old value
+                Instruction::Put | Instruction::Get => { overwritten.remove(&offset); },
+                Instruction::PutN { count, stride } | Instruction::GetN { count, stride } => {
+                    // the pointer ends on the last cell
+                    offset -= (*count as isize - 1) * stride;
+                    for n in 0..*count as isize {
+                        overwritten.remove(&(offset + n * stride));
+                    }
+                },
+                Instruction::MulAdd(updates) => {
+                    removed[i] = overwritten.contains(&offset)
+                        && updates.iter().all(|(at, _)| overwritten.contains(&(offset + at)));
+                    if !removed[i] {
+                        overwritten.remove(&offset);
+                        for (at, _) in updates {
+                            overwritten.remove(&(offset + at));
+                        }
+                    }
+                },
+                Instruction::PutStr(_) => {},
+                Instruction::Jmp(_) | Instruction::JmpZ(_) | Instruction::If(_) | Instruction::Exit
+                | Instruction::NextTape(_) | Instruction::PrevTape(_) => {
+                    overwritten.clear();
+                    offset = 0;
                 },
-                _ => {},
             }
         }
+        if !removed.contains(&true) { return; }
 
-        optimized_instructions.shrink_to_fit();
+        // the moves around a removed write stay, they can still run past an end of the tape
+        let mut optimized_instructions = Vec::with_capacity(self.instructions.len());
+        let mut new_addrs = Vec::with_capacity(self.instructions.len());
+        for (i, instr) in self.instructions.iter().enumerate() {
+            new_addrs.push(optimized_instructions.len());
+            if !removed[i] {
+                optimized_instructions.push(instr.clone());
+            }
+        }
+
+        for instr in &mut optimized_instructions {
+            if let Instruction::Jmp(addr) | Instruction::JmpZ(addr) | Instruction::If(addr) = instr {
+                *addr = new_addrs[*addr];
+            }
+        }
         self.instructions = optimized_instructions;
     }
+
+    /// turn runs of `Put` or `Get`, that are separated by the same move, into a single `PutN` or `GetN`
+    fn batch_io(&mut self) {
+        let mut optimized_instructions = Vec::with_capacity(self.instructions.len());
+        // a jump can only lead to the first instruction of a run
+        let mut new_addrs = Vec::with_capacity(self.instructions.len());
+        let mut i = 0;
+        while i < self.instructions.len() {
+            let (count, stride, len) = self.io_run(i);
+            new_addrs.extend(std::iter::repeat_n(optimized_instructions.len(), len));
+            let instr = match &self.instructions[i] {
+                Instruction::Put if count > 1 => Instruction::PutN { count, stride },
+                Instruction::Get if count > 1 => Instruction::GetN { count, stride },
+                instr => instr.clone(),
+            };
+            optimized_instructions.push(instr);
+            i += len;
+        }
+
+        for instr in &mut optimized_instructions {
+            if let Instruction::Jmp(addr) | Instruction::JmpZ(addr) = instr {
+                *addr = new_addrs[*addr];
+            }
+        }
+        self.instructions = optimized_instructions;
+    }
+
+    /// count, stride and instructions of the run of `Put`s or `Get`s at `start`, a count of 1 is no run
+    fn io_run(&self, start: usize) -> (usize, isize, usize) {
+        let io = &self.instructions[start];
+        if !matches!(io, Instruction::Put | Instruction::Get) {
+            return (1, 0, 1);
+        }
+        let step = |at: usize| -> Option<(isize, usize)> {
+            match self.instructions.get(at)? {
+                instr if instr == io => Some((0, 1)),
+                Instruction::MvRight(times) if self.instructions.get(at + 1) == Some(io) => Some((*times as isize, 2)),
+                Instruction::MvLeft(times) if self.instructions.get(at + 1) == Some(io) => Some((-(*times as isize), 2)),
+                _ => None,
+            }
+        };
+
+        let Some((stride, _)) = step(start + 1) else { return (1, 0, 1); };
+        let (mut count, mut len) = (1, 1);
+        while let Some((next_stride, next_len)) = step(start + len) {
+            if next_stride != stride { break; }
+            count += 1;
+            len += next_len;
+        }
+        (count, stride, len)
+    }
+
+    /// turn loops, whose body always leaves the condition cell zero, into `If`s without the jump back
+    /// has to run after the other passes, which only know about `Jmp` and `JmpZ`
+    fn lower_run_once_loops(&mut self) {
+        let lowered: HashSet<usize> = (0..self.instructions.len())
+            .filter(|start| self.zeroes_condition(*start))
+            .collect();
+        if lowered.is_empty() { return; }
+
+        // the removed `Jmp`s map to the instruction after them
+        let mut new_addrs = Vec::with_capacity(self.instructions.len());
+        let mut removed = 0;
+        for instr in &self.instructions {
+            new_addrs.push(new_addrs.len() - removed);
+            if let Instruction::Jmp(start) = instr {
+                if lowered.contains(start) { removed += 1; }
+            }
+        }
+
+        let mut optimized_instructions = Vec::with_capacity(self.instructions.len() - removed);
+        for (i, instr) in self.instructions.iter().enumerate() {
+            let instr = match instr {
+                Instruction::Jmp(start) if lowered.contains(start) => continue,
+                Instruction::JmpZ(end) if lowered.contains(&i) => Instruction::If(new_addrs[*end]),
+                Instruction::JmpZ(end) => Instruction::JmpZ(new_addrs[*end]),
+                Instruction::Jmp(start) => Instruction::Jmp(new_addrs[*start]),
+                other => other.clone(),
+            };
+            optimized_instructions.push(instr);
+        }
+
+        self.instructions = optimized_instructions;
+    }
+
+    /// true if `start` is a loop, whose body leaves the pointer at the condition cell and that cell zero on every path
+    fn zeroes_condition(&self, start: usize) -> bool {
+        let Some(Instruction::JmpZ(end)) = self.instructions.get(start) else { return false; };
+        let mut offset = 0isize;
+        let mut zeroed = false;
+        let mut i = start + 1;
+        while i < *end {
+            match &self.instructions[i] {
+                Instruction::MvLeft(times) => offset -= *times as isize,
+                Instruction::MvRight(times) => offset += *times as isize,
+                Instruction::Set(value) if offset == 0 => zeroed = *value == 0,
+                Instruction::MulAdd(updates) => {
+                    if offset == 0 {
+                        zeroed = true;
+                    } else if updates.iter().any(|(target, _)| offset + target == 0) {
+                        zeroed = false;
+                    }
+                },
+                Instruction::NextTape(_) | Instruction::PrevTape(_) => return false,
+                Instruction::PutN { count, stride } => offset += (*count as isize - 1) * stride,
+                // a `GetN` also moves the pointer, so it isn't handled like the other writes below
+                Instruction::GetN { count, stride } => {
+                    if (0..*count as isize).any(|cell| offset + cell * stride == 0) {
+                        zeroed = false;
+                    }
+                    offset += (*count as isize - 1) * stride;
+                },
+                instr if offset == 0 && instr.writes_cell() => zeroed = false,
+                Instruction::JmpZ(nested_end) => {
+                    let Some(writes) = self.loop_writes(i) else { return false; };
+                    if offset == 0 {
+                        // a loop is only left with a zero cell
+                        zeroed = true;
+                    } else if writes.contains(&-offset) {
+                        zeroed = false;
+                    }
+                    i = nested_end + 1;
+                    continue;
+                },
+                _ => {},
+            }
+            i += 1;
+        }
+        zeroed && offset == 0
+    }
+
+    /// offsets written by the loop at `start`, relative to the pointer at its start
+    /// `None` if the loop doesn't return the pointer to where it started, or switches tapes
+    fn loop_writes(&self, start: usize) -> Option<HashSet<isize>> {
+        let Some(Instruction::JmpZ(end)) = self.instructions.get(start) else { return None; };
+        let mut writes = HashSet::new();
+        let mut offset = 0isize;
+        let mut i = start + 1;
+        while i < *end {
+            match &self.instructions[i] {
+                Instruction::MvLeft(times) => offset -= *times as isize,
+                Instruction::MvRight(times) => offset += *times as isize,
+                Instruction::NextTape(_) | Instruction::PrevTape(_) => return None,
+                Instruction::PutN { count, stride } => offset += (*count as isize - 1) * stride,
+                Instruction::GetN { count, stride } => {
+                    writes.extend((0..*count as isize).map(|cell| offset + cell * stride));
+                    offset += (*count as isize - 1) * stride;
+                },
+                Instruction::JmpZ(nested_end) => {
+                    writes.extend(self.loop_writes(i)?.into_iter().map(|write| write + offset));
+                    i = nested_end + 1;
+                    continue;
+                },
+                Instruction::MulAdd(updates) => {
+                    writes.insert(offset);
+                    writes.extend(updates.iter().map(|(target, _)| offset + target));
+                },
+                instr if instr.writes_cell() => { writes.insert(offset); },
+                _ => {},
+            }
+            i += 1;
+        }
+        (offset == 0).then_some(writes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::MachineBuilder;
+
+    fn compile(source: &str, optimize: bool, passes: &[Pass]) -> Program {
+        Program::compile(source, &CompileOptions { optimize, passes: passes.to_vec(), ..Default::default() }).unwrap()
+    }
+
+    fn optimized(source: &str, passes: &[Pass]) -> Vec<Instruction> {
+        compile(source, true, passes).to_vec()
+    }
+
+    /// run the program with and without the passes, both have to print the same
+    fn same_output(source: &str, passes: &[Pass], input: &[u8]) -> Vec<u8> {
+        let [plain, optimized] = [false, true].map(|optimize| {
+            let program = compile(source, optimize, passes);
+            let mut machine = MachineBuilder::new().input(input).build();
+            machine.close_stdin();
+            machine.capture_output();
+            machine.run(&program).unwrap();
+            machine.take_output()
+        });
+        assert_eq!(plain, optimized, "{source}");
+        plain
+    }
+
+    #[test]
+    fn constants_fold_into_set() {
+        let passes = [Pass::ConstantPropagation];
+        // the moves in front of the first `Set` check, that the tape reaches the cell right of the start
+        assert_eq!(optimized("++++>+++++<-->+", &passes), [
+            Instruction::MvRight(1), Instruction::MvLeft(1), Instruction::Set(2), Instruction::MvRight(1), Instruction::Set(6), Instruction::Exit,
+        ]);
+        assert_eq!(same_output("++++>+++++<-->+<.>.", &passes, &[]), [2, 6]);
+        // the value read by `,` isn't known, so only the cell to the right folds
+        assert_eq!(optimized(",+>++<.", &passes), [
+            Instruction::Get, Instruction::Inc(1), Instruction::MvRight(1), Instruction::Set(2), Instruction::MvLeft(1), Instruction::Put, Instruction::Exit,
+        ]);
+        assert_eq!(same_output(",+>++<.>.", &passes, b"A"), b"B\x02");
+    }
+
+    #[test]
+    fn updates_are_sorted_by_cell() {
+        let passes = [Pass::PointerMoves];
+        // `<+>+<<+>>>-` moves 7 cells, the sweep from the leftmost cell to the right only 5
+        assert_eq!(optimized(">,>,<+>+<<+>>>-", &passes), [
+            Instruction::MvRight(1), Instruction::Get, Instruction::MvRight(1), Instruction::Get,
+            Instruction::MvLeft(2), Instruction::Inc(1), Instruction::MvRight(1), Instruction::Inc(1),
+            Instruction::MvRight(1), Instruction::Inc(1), Instruction::MvRight(1), Instruction::Dec(1), Instruction::Exit,
+        ]);
+        assert_eq!(same_output(">,>,<+>+<<+>>>-<<<.>.>.>.", &passes, b"ab"), b"\x01bc\xff");
+        // updates of the same cell keep their order and are merged
+        assert_eq!(optimized("+>+<+", &passes), [Instruction::Inc(2), Instruction::MvRight(1), Instruction::Inc(1), Instruction::MvLeft(1), Instruction::Exit]);
+    }
+
+    #[test]
+    fn known_output_becomes_put_str() {
+        let passes = [Pass::MulAdd, Pass::ConstantPropagation];
+        let source = "++++++++[>++++++++<-]>+.+.+.";
+        assert_eq!(optimized(source, &passes), [
+            Instruction::MvRight(1), Instruction::PutStr(b"ABC".to_vec()),
+            Instruction::MvLeft(1), Instruction::Set(0), Instruction::MvRight(1), Instruction::Set(67), Instruction::Exit,
+        ]);
+        assert_eq!(same_output(source, &passes, &[]), b"ABC");
+        // an unknown cell ends the string
+        let instructions = optimized("+.,.+.", &passes);
+        assert_eq!(instructions.iter().filter(|instr| matches!(instr, Instruction::PutStr(_))).count(), 1, "{instructions:?}");
+        assert!(instructions.contains(&Instruction::Put), "{instructions:?}");
+        assert_eq!(same_output("+.,.+.", &passes, b"a"), b"\x01ab");
+    }
+
+    #[test]
+    fn io_runs_are_batched() {
+        let passes = [Pass::IoBatching];
+        assert_eq!(optimized(",...", &passes), [Instruction::Get, Instruction::PutN { count: 3, stride: 0 }, Instruction::Exit]);
+        assert_eq!(same_output(",...", &passes, b"a"), b"aaa");
+        let source = ">>,<,<,>>.<.<.";
+        assert_eq!(optimized(source, &passes), [
+            Instruction::MvRight(2), Instruction::GetN { count: 3, stride: -1 },
+            Instruction::MvRight(2), Instruction::PutN { count: 3, stride: -1 }, Instruction::Exit,
+        ]);
+        assert_eq!(same_output(source, &passes, b"abc"), b"abc");
+        // a `.` in between is no longer a run of `,`
+        assert_eq!(same_output(",>,.<,.", &passes, b"abc"), b"bc");
+    }
+
+    #[test]
+    fn overwritten_stores_are_removed() {
+        let passes = [Pass::MulAdd, Pass::ConstantPropagation, Pass::DeadStores];
+        // in the loop the cell isn't known, so `+++` stays an `Inc`, that the `Set` of `[-]+` overwrites
+        let source = ",[>+++[-]+.<-]";
+        assert!(optimized(source, &passes[..2]).contains(&Instruction::Inc(3)));
+        assert_eq!(optimized(source, &passes), [
+            Instruction::Get, Instruction::JmpZ(9), Instruction::MvRight(1), Instruction::PutStr(vec![1]), Instruction::MvLeft(1),
+            Instruction::Dec(1), Instruction::MvRight(1), Instruction::Set(1), Instruction::MvLeft(1), Instruction::Jmp(1), Instruction::Exit,
+        ]);
+        assert_eq!(same_output(source, &passes, &[3]), [1, 1, 1]);
+        // `,` consumes the input, even if its value is overwritten
+        let instructions = optimized(",>,[-]+<.>.", &passes);
+        assert_eq!(instructions.iter().filter(|instr| matches!(instr, Instruction::Get)).count(), 2, "{instructions:?}");
+        assert_eq!(same_output(",>,[-]+<.>.", &passes, b"ab"), b"a\x01");
+    }
+
+    #[test]
+    fn balanced_loops_become_mul_add() {
+        let instructions = optimized(",[->+++>>--<<<]>.>>.", &Pass::ALL);
+        assert!(instructions.contains(&Instruction::MulAdd(vec![(1, 3), (3, 254)])), "{instructions:?}");
+        assert!(!instructions.iter().any(|instr| matches!(instr, Instruction::JmpZ(_) | Instruction::Jmp(_))), "{instructions:?}");
+        assert_eq!(same_output(",[->+++>>--<<<]>.>>.", &Pass::ALL, &[100]), [44, 56]);
+        // the counter isn't decremented by one, so the loop stays
+        let instructions = optimized(",[-->+<]>.", &Pass::ALL);
+        assert!(instructions.iter().any(|instr| matches!(instr, Instruction::JmpZ(_))), "{instructions:?}");
+        same_output(",[-->+<]>.", &Pass::ALL, &[100]);
+    }
+
+    #[test]
+    fn mul_add_is_kept_out_of_checked_cells() {
+        let options = CompileOptions { optimize: true, overflow: OverflowPolicy::Error, ..Default::default() };
+        let instructions = Program::compile(",[->+++<]>.", &options).unwrap().to_vec();
+        assert!(!instructions.iter().any(|instr| matches!(instr, Instruction::MulAdd(_))), "{instructions:?}");
+    }
+
+    #[test]
+    fn broken_passes_are_reported() {
+        let mut program = Program::from_str("+[-].", false).unwrap();
+        let result = program.run_pass("broken", |program| { program.instructions.pop(); });
+        assert_eq!(result, Err(ValidationError::MissingExit));
+        let mut program = Program::from_str("+[-].", false).unwrap();
+        let result = program.run_pass("broken", |program| program.instructions[3] = Instruction::Put);
+        assert_eq!(result, Err(ValidationError::UnmatchedJmpZ { at: 1 }));
+
+        let err = ParseError::invalid(DEFAULT_TAB_WIDTH, Pass::DeadStores, ValidationError::MissingExit);
+        assert_eq!(err.validation(), Some((Pass::DeadStores, &ValidationError::MissingExit)));
+        assert_eq!(err.to_string(), "1:1: Optimizer pass dead-stores broke the program: The program doesn't end with an Exit instruction");
+    }
+
+    #[test]
+    fn loops_reading_past_the_condition_stay_loops() {
+        let instructions = optimized(",[,>,[-]]", &Pass::ALL);
+        assert!(instructions.contains(&Instruction::GetN { count: 2, stride: 1 }), "{instructions:?}");
+        assert!(!instructions.iter().any(|instr| matches!(instr, Instruction::If(_))), "{instructions:?}");
+    }
 }
@@ -0,0 +1,53 @@
+//! Make the Windows console behave like a unix terminal, everywhere else this does nothing
+
+/// Prepare the console before anything is printed or read:
+/// ANSI escape sequences (e.g. of `--heatmap`) are interpreted, and bytes are read and written as UTF-8
+/// returns false if the console doesn't support escape sequences (before Windows 10)
+pub fn init() -> bool {
+    #[cfg(windows)]
+    return windows::init();
+    #[cfg(not(windows))]
+    true
+}
+
+/// true if `,` reads from a console, that ends lines with `\r\n` instead of `\n`
+pub(crate) fn crlf_input() -> bool {
+    cfg!(windows) && std::io::IsTerminal::is_terminal(&std::io::stdin())
+}
+
+#[cfg(windows)]
+mod windows {
+    use core::ffi::c_void;
+
+    type Handle = *mut c_void;
+
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+    const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+    const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+    const CP_UTF8: u32 = 65001;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetStdHandle(std_handle: u32) -> Handle;
+        fn GetConsoleMode(handle: Handle, mode: *mut u32) -> i32;
+        fn SetConsoleMode(handle: Handle, mode: u32) -> i32;
+        fn SetConsoleCP(code_page: u32) -> i32;
+        fn SetConsoleOutputCP(code_page: u32) -> i32;
+    }
+
+    pub fn init() -> bool {
+        // SAFETY: the calls only take plain values and a pointer to a local, invalid handles make them fail
+        unsafe {
+            SetConsoleCP(CP_UTF8);
+            SetConsoleOutputCP(CP_UTF8);
+            // a redirected stream isn't a console and needs no processing
+            [STD_OUTPUT_HANDLE, STD_ERROR_HANDLE].into_iter().all(|std_handle| {
+                let handle = GetStdHandle(std_handle);
+                let mut mode = 0;
+                GetConsoleMode(handle, &mut mode) == 0
+                    || mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+                    || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+            })
+        }
+    }
+}
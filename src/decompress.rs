@@ -0,0 +1,62 @@
+use std::io;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use std::io::Read;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression formats that are detected when reading program files
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// detect the compression by the magic bytes at the start of the file
+    pub fn detect(bytes: &[u8]) -> Option<Compression> {
+        if bytes.starts_with(&GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(&ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// decompress the file contents if they are compressed, otherwise return them unchanged
+/// fails if the file is compressed, but support for the format wasn't compiled in
+pub fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>, io::Error> {
+    match Compression::detect(&bytes) {
+        None => Ok(bytes),
+        Some(Compression::Gzip) => decompress_gzip(&bytes),
+        Some(Compression::Zstd) => decompress_zstd(&bytes),
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn decompress_gzip(bytes: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut decompressed = Vec::new();
+    flate2::read::MultiGzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn decompress_gzip(_bytes: &[u8]) -> Result<Vec<u8>, io::Error> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "program file is gzip compressed, which requires the `gzip` feature"))
+}
+
+#[cfg(feature = "zstd")]
+fn decompress_zstd(bytes: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut decompressed = Vec::new();
+    ruzstd::decoding::StreamingDecoder::new(bytes)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?
+        .read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress_zstd(_bytes: &[u8]) -> Result<Vec<u8>, io::Error> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "program file is zstd compressed, which requires the `zstd` feature"))
+}
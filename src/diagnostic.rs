@@ -0,0 +1,158 @@
+use clap::ValueEnum;
+
+use crate::compiler::Position;
+
+/// Lines shown above and below the error with `ErrorStyle::Context`
+const CONTEXT_LINES: usize = 2;
+
+/// How diagnostics are printed
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum ErrorStyle {
+    /// one `file:line:col: message` line per diagnostic
+    Short,
+    /// the message with the offending line
+    #[default]
+    Full,
+    /// the message with the surrounding lines
+    Context,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A secondary location, that is shown together with the main one
+#[derive(Debug, PartialEq, Clone)]
+pub struct Label {
+    pub pos: Position,
+    pub message: String,
+}
+
+/// Structured error or warning pointing into the source
+/// renderers (see `render`) decide how much of the source is shown
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub pos: Position,
+    pub message: String,
+    pub help: Option<String>,
+    pub labels: Vec<Label>,
+}
+
+impl Diagnostic {
+    pub fn error(pos: Position, message: String) -> Self {
+        Diagnostic { severity: Severity::Error, pos, message, help: None, labels: Vec::new() }
+    }
+
+    pub fn warning(pos: Position, message: String) -> Self {
+        Diagnostic { severity: Severity::Warning, ..Diagnostic::error(pos, message) }
+    }
+
+    fn prefix(&self) -> &'static str {
+        match self.severity {
+            Severity::Error => "",
+            Severity::Warning => "warning: ",
+        }
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help = Some(help);
+        self
+    }
+
+    pub fn with_label(mut self, pos: Position, message: &str) -> Self {
+        self.labels.push(Label { pos, message: message.to_string() });
+        self
+    }
+
+    /// render the message with the source line and a caret, followed by the help and the labels
+    pub fn render(&self, source: &str, tab_width: usize) -> String {
+        self.render_with(source, tab_width, 0)
+    }
+
+    /// render the diagnostic in the given style, `file` is only used by `ErrorStyle::Short`
+    pub fn render_style(&self, style: ErrorStyle, file: &str, source: &str, tab_width: usize) -> String {
+        match style {
+            ErrorStyle::Short => self.render_short(file),
+            ErrorStyle::Full => self.render(source, tab_width),
+            ErrorStyle::Context => self.render_with(source, tab_width, CONTEXT_LINES),
+        }
+    }
+
+    /// `file:line:col: message`, the help gets its own line at the first label
+    pub fn render_short(&self, file: &str) -> String {
+        let mut msg = format!("{file}:{}:{}: {}{}\n", self.pos.line, self.pos.col, self.prefix(), self.message);
+        if let Some(help) = &self.help {
+            let pos = self.labels.first().map_or(self.pos, |label| label.pos);
+            msg.push_str(&format!("{file}:{}:{}: help: {help}\n", pos.line, pos.col));
+        }
+        msg
+    }
+
+    fn render_with(&self, source: &str, tab_width: usize, context: usize) -> String {
+        let mut msg = format!("{}{} at {}:{}: \n {}\n", self.prefix(), self.message, self.pos.line, self.pos.col, format_context(self.pos, source, tab_width, context));
+        if let Some(help) = &self.help {
+            msg.push_str(&format!("help: {help}\n"));
+        }
+        for label in &self.labels {
+            msg.push_str(&format!(" {} {}\n", format_snippet(label.pos, line_of(source, label.pos), tab_width), label.message));
+        }
+        msg
+    }
+}
+
+fn line_of(source: &str, pos: Position) -> &str {
+    source.lines().nth(pos.line - 1).unwrap_or("")
+}
+
+/// render the source line with a caret under the display column of `pos`
+/// tabs are expanded, so the caret lines up independent of the tab width of the terminal
+pub fn format_snippet(pos: Position, line_str: &str, tab_width: usize) -> String {
+    let line = pos.line;
+    let mut error_str = format!("{line} {}", expand_tabs(line_str, tab_width));
+    let ln_len = line.to_string().len();
+    let arrow = pos.col + ln_len;
+    error_str.push_str("\n ");
+    error_str.push_str(&" ".repeat(arrow));
+    error_str.push('^');
+    error_str
+}
+
+/// like `format_snippet`, but with up to `context` lines above and below the source line
+fn format_context(pos: Position, source: &str, tab_width: usize, context: usize) -> String {
+    if context == 0 {
+        return format_snippet(pos, line_of(source, pos), tab_width);
+    }
+
+    let lines: Vec<&str> = source.lines().collect();
+    let first = pos.line.saturating_sub(context).max(1);
+    let last = (pos.line + context).min(lines.len()).max(pos.line);
+    let ln_len = last.to_string().len();
+
+    let mut rows = Vec::new();
+    for line in first..=last {
+        let line_str = lines.get(line - 1).copied().unwrap_or("");
+        rows.push(format!("{line:>ln_len$} {}", expand_tabs(line_str, tab_width)));
+        if line == pos.line {
+            rows.push(format!("{}^", " ".repeat(ln_len + pos.col)));
+        }
+    }
+    rows.join("\n ")
+}
+
+fn expand_tabs(line_str: &str, tab_width: usize) -> String {
+    let mut expanded = String::with_capacity(line_str.len());
+    let mut col = 1;
+    for ch in line_str.chars() {
+        let next = Position::advance_col(col, ch, tab_width);
+        if ch == '\t' {
+            expanded.push_str(&" ".repeat(next - col));
+        } else {
+            expanded.push(ch);
+        }
+        col = next;
+    }
+    expanded
+}
@@ -0,0 +1,159 @@
+use std::ops::Range;
+
+use crate::compiler::{CompileOptions, Position};
+use crate::syntax::SourceFile;
+
+/// A canonical instruction: a run of the same command, brackets and i/o always stand alone
+#[derive(Debug, PartialEq, Clone)]
+pub struct Run {
+    pub command: char,
+    pub count: usize,
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Run {
+    fn text(&self) -> String {
+        self.command.to_string().repeat(self.count)
+    }
+}
+
+/// A region, where the two programs differ
+/// `removed` indexes the runs of the first program, `inserted` those of the second
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Hunk {
+    pub removed: Range<usize>,
+    pub inserted: Range<usize>,
+}
+
+/// Structural difference of two programs, that ignores comments and formatting
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProgramDiff {
+    pub old: Vec<Run>,
+    pub new: Vec<Run>,
+    pub hunks: Vec<Hunk>,
+}
+
+impl ProgramDiff {
+    pub fn new(old: &str, new: &str, options: &CompileOptions) -> Self {
+        let old = runs(old, options);
+        let new = runs(new, options);
+        let hunks = hunks(&old, &new);
+        ProgramDiff { old, new, hunks }
+    }
+
+    /// every hunk with the positions in both sources and the commands, that were removed (`-`) and inserted (`+`)
+    pub fn render(&self, old_name: &str, new_name: &str) -> String {
+        if self.hunks.is_empty() {
+            return String::from("no structural differences");
+        }
+
+        let mut out = String::new();
+        for hunk in &self.hunks {
+            let removed = &self.old[hunk.removed.clone()];
+            let inserted = &self.new[hunk.inserted.clone()];
+            match (removed.is_empty(), inserted.is_empty()) {
+                (false, false) => out.push_str(&format!("changed {old_name} {} -> {new_name} {}\n", span(removed), span(inserted))),
+                (false, true) => out.push_str(&format!("removed {old_name} {}\n", span(removed))),
+                _ => out.push_str(&format!("inserted {new_name} {}\n", span(inserted))),
+            }
+            if !removed.is_empty() {
+                out.push_str(&format!("- {}\n", removed.iter().map(Run::text).collect::<String>()));
+            }
+            if !inserted.is_empty() {
+                out.push_str(&format!("+ {}\n", inserted.iter().map(Run::text).collect::<String>()));
+            }
+        }
+        out.pop();
+        out
+    }
+}
+
+/// source range covered by the runs, which must not be empty
+fn span(runs: &[Run]) -> String {
+    let (start, end) = (runs[0].start, runs[runs.len() - 1].end);
+    format!("{}:{}..{}:{}", start.line, start.col, end.line, end.col)
+}
+
+/// the canonical instruction stream of a source
+fn runs(source: &str, options: &CompileOptions) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+    for token in SourceFile::parse(source, options).tokens {
+        match runs.last_mut() {
+            Some(run) if run.command == token.command && "+-<>{}".contains(token.command) => {
+                run.count += 1;
+                run.end = token.pos;
+            },
+            _ => runs.push(Run { command: token.command, count: 1, start: token.pos, end: token.pos }),
+        }
+    }
+    runs
+}
+
+fn same(old: &Run, new: &Run) -> bool {
+    old.command == new.command && old.count == new.count
+}
+
+/// shortest edit script with the algorithm of Myers, grouped into hunks
+fn hunks(old: &[Run], new: &[Run]) -> Vec<Hunk> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let offset = n + m + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    // the furthest x of every diagonal -d..=d after round d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=n + m {
+        for k in (-d..=d).step_by(2) {
+            let down = k == -d || (k != d && v[(offset + k - 1) as usize] < v[(offset + k + 1) as usize]);
+            let mut x = if down { v[(offset + k + 1) as usize] } else { v[(offset + k - 1) as usize] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && same(&old[x as usize], &new[y as usize]) {
+                x += 1;
+                y += 1;
+            }
+            v[(offset + k) as usize] = x;
+            if x >= n && y >= m {
+                trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+                break 'search;
+            }
+        }
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+    }
+
+    // walk back from the end and collect the matching pairs
+    let mut matches = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev() {
+        let previous = &trace[d as usize - 1];
+        let at = |k: isize| previous[(k + d - 1) as usize];
+        let k = x - y;
+        let down = k == -d || (k != d && at(k - 1) < at(k + 1));
+        let prev_k = if down { k + 1 } else { k - 1 };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            matches.push((x as usize, y as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        matches.push((x as usize, y as usize));
+    }
+    matches.reverse();
+
+    let mut hunks = Vec::new();
+    let (mut old_at, mut new_at) = (0, 0);
+    for (old_match, new_match) in matches.into_iter().chain([(old.len(), new.len())]) {
+        if old_at < old_match || new_at < new_match {
+            hunks.push(Hunk { removed: old_at..old_match, inserted: new_at..new_match });
+        }
+        old_at = old_match + 1;
+        new_at = new_match + 1;
+    }
+    hunks
+}
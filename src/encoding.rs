@@ -0,0 +1,108 @@
+use clap::ValueEnum;
+use core::fmt::Display;
+
+use crate::compiler::{DEFAULT_TAB_WIDTH, Position};
+use crate::diagnostic::format_snippet;
+
+/// Encodings for inline programs, for channels that mangle the bf command characters
+#[derive(Debug, PartialEq, Eq, Clone, Copy, ValueEnum)]
+pub enum Encoding {
+    Hex,
+    Base64,
+}
+
+/// Error while decoding an encoded program, pointing at the offending character
+#[derive(Debug)]
+pub struct DecodeError {
+    input: String,
+    pos: usize,
+    msg: String,
+}
+
+impl DecodeError {
+    fn new(input: &str, pos: usize, msg: String) -> Self {
+        DecodeError { input: input.to_string(), pos, msg }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pos = Position::of_char(&self.input, self.pos, DEFAULT_TAB_WIDTH);
+        let line_str = self.input.lines().nth(pos.line - 1).unwrap_or("");
+        write!(f, "Error while decoding the program at {}:{}: {}\n {}", pos.line, pos.col, self.msg, format_snippet(pos, line_str, DEFAULT_TAB_WIDTH))
+    }
+}
+
+impl Encoding {
+    /// decode the program text, whitespace between the encoded characters is ignored
+    pub fn decode(&self, input: &str) -> Result<String, DecodeError> {
+        let bytes = match self {
+            Encoding::Hex => decode_hex(input)?,
+            Encoding::Base64 => decode_base64(input)?,
+        };
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+fn decode_hex(input: &str) -> Result<Vec<u8>, DecodeError> {
+    let mut bytes = Vec::with_capacity(input.len() / 2);
+    let mut high: Option<(usize, u8)> = None;
+
+    for (pos, ch) in input.chars().enumerate() {
+        if ch.is_whitespace() { continue; }
+        let Some(digit) = ch.to_digit(16) else {
+            return Err(DecodeError::new(input, pos, format!("'{ch}' is not a hex digit")));
+        };
+        match high.take() {
+            Some((_, high)) => bytes.push(high << 4 | digit as u8),
+            None => high = Some((pos, digit as u8)),
+        }
+    }
+
+    if let Some((pos, _)) = high {
+        return Err(DecodeError::new(input, pos, String::from("odd number of hex digits, this digit has no partner")));
+    }
+    Ok(bytes)
+}
+
+fn decode_base64(input: &str) -> Result<Vec<u8>, DecodeError> {
+    let mut bytes = Vec::with_capacity(input.len() / 4 * 3);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    let mut padding: Option<usize> = None;
+
+    for (pos, ch) in input.chars().enumerate() {
+        if ch.is_whitespace() { continue; }
+        if ch == '=' {
+            padding.get_or_insert(pos);
+            continue;
+        }
+        if padding.is_some() {
+            return Err(DecodeError::new(input, pos, format!("unexpected '{ch}' after padding")));
+        }
+
+        // accept both the standard and the url safe alphabet
+        let value = match ch {
+            'A'..='Z' => ch as u32 - 'A' as u32,
+            'a'..='z' => ch as u32 - 'a' as u32 + 26,
+            '0'..='9' => ch as u32 - '0' as u32 + 52,
+            '+' | '-' => 62,
+            '/' | '_' => 63,
+            _ => return Err(DecodeError::new(input, pos, format!("'{ch}' is not a base64 character"))),
+        };
+        buffer = buffer << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    // a single leftover character can't encode a full byte
+    if bits >= 6 {
+        let pos = input.chars().count().saturating_sub(1);
+        return Err(DecodeError::new(input, pos, String::from("truncated base64 input")));
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,224 @@
+use clap::ValueEnum;
+use std::collections::HashMap;
+
+use crate::compiler::{CompileOptions, Instruction, Pass, Program};
+use crate::tape::{Tape, VecTape};
+use crate::vm::{check_program, Machine, RunLimits, RuntimeError};
+
+/// Backend, that executes a compiled program
+/// tools driving a program only go through this trait, so they work with every backend
+pub trait Engine<T: Tape = VecTape> {
+    /// take the program to execute, replacing a previously loaded one
+    fn load(&mut self, program: &Program);
+
+    /// run the loaded program until it ends or one of the limits is exceeded
+    fn run(&mut self, limits: RunLimits) -> Result<(), RuntimeError>;
+
+    /// execute a single instruction, `false` once the program ended
+    fn step(&mut self) -> Result<bool, RuntimeError>;
+
+    /// position of the next instruction in the loaded program
+    /// while the tiered engine runs an optimized loop, this is the start of the loop
+    fn instr_ptr(&self) -> usize;
+
+    /// the instruction, that the next step executes, which is part of an optimized loop in the tiered engine
+    fn next_instruction(&self) -> &Instruction;
+
+    /// the machine, the program runs on
+    fn machine(&mut self) -> &mut Machine<T>;
+}
+
+/// Backends selectable on the command line
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum EngineKind {
+    /// the instruction interpreter
+    #[default]
+    Interp,
+    /// the interpreter, that optimizes every loop once it ran often enough
+    Tiered,
+}
+
+/// Engine, that interprets the instructions on a `Machine`
+pub struct Interpreter<'m, T: Tape = VecTape> {
+    machine: &'m mut Machine<T>,
+    instructions: Vec<Instruction>,
+    instr_ptr: usize,
+}
+
+impl<'m, T: Tape> Interpreter<'m, T> {
+    pub fn new(machine: &'m mut Machine<T>) -> Self {
+        Interpreter { machine, instructions: vec![Instruction::Exit], instr_ptr: 0 }
+    }
+}
+
+impl<T: Tape> Engine<T> for Interpreter<'_, T> {
+    fn load(&mut self, program: &Program) {
+        self.instructions = program.to_vec();
+        self.instr_ptr = 0;
+    }
+
+    fn run(&mut self, limits: RunLimits) -> Result<(), RuntimeError> {
+        self.machine.set_limits(limits);
+        check_program(&self.instructions)?;
+        while self.step()? {}
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<bool, RuntimeError> {
+        match self.machine.step(&self.instructions, self.instr_ptr)? {
+            Some(next) => {
+                self.instr_ptr = next;
+                Ok(true)
+            },
+            None => {
+                self.machine.finish()?;
+                Ok(false)
+            },
+        }
+    }
+
+    fn instr_ptr(&self) -> usize {
+        self.instr_ptr
+    }
+
+    fn next_instruction(&self) -> &Instruction {
+        &self.instructions[self.instr_ptr]
+    }
+
+    fn machine(&mut self) -> &mut Machine<T> {
+        self.machine
+    }
+}
+
+/// Iterations of a loop, after which the tiered engine optimizes it
+pub const HOT_LOOP_ITERATIONS: u32 = 1000;
+
+/// Engine, that starts with the plain instructions and counts how often every loop repeats
+/// a hot loop is optimized on its own and entered at its start, the next time the program gets there,
+/// once the optimized loop ends, the plain instructions continue after its end
+pub struct Tiered<'m, T: Tape = VecTape> {
+    machine: &'m mut Machine<T>,
+    instructions: Vec<Instruction>,
+    instr_ptr: usize,
+    hot_after: u32,
+    /// options for optimizing a hot loop, the overflow policy is the one of the machine
+    options: CompileOptions,
+    /// repetitions of every loop by its start
+    iterations: HashMap<usize, u32>,
+    /// optimized loops by their start, `None` if the loop can't be optimized
+    compiled: HashMap<usize, Option<Vec<Instruction>>>,
+    /// start of the optimized loop, that is running, and the instruction pointer inside of it
+    running: Option<(usize, usize)>,
+}
+
+impl<'m, T: Tape> Tiered<'m, T> {
+    pub fn new(machine: &'m mut Machine<T>) -> Self {
+        let options = CompileOptions { overflow: machine.overflow_policy(), ..Default::default() };
+        Tiered {
+            machine,
+            instructions: vec![Instruction::Exit],
+            instr_ptr: 0,
+            hot_after: HOT_LOOP_ITERATIONS,
+            options,
+            iterations: HashMap::new(),
+            compiled: HashMap::new(),
+            running: None,
+        }
+    }
+
+    /// optimize a loop after `iterations` repetitions instead of `HOT_LOOP_ITERATIONS`
+    pub fn hot_after(mut self, iterations: u32) -> Self {
+        self.hot_after = iterations;
+        self
+    }
+
+    /// run only these optimizer passes on a hot loop instead of all of them
+    pub fn passes(mut self, passes: Vec<Pass>) -> Self {
+        self.options.passes = passes;
+        self
+    }
+
+    /// count a repetition of the loop at `start` and optimize it, once it got hot
+    fn repeat(&mut self, start: usize) {
+        if self.compiled.contains_key(&start) {
+            return;
+        }
+        let iterations = self.iterations.entry(start).or_insert(0);
+        *iterations += 1;
+        if *iterations >= self.hot_after {
+            let compiled = Program::compile_loop(&self.instructions, start, &self.options).map(|program| program.to_vec());
+            self.compiled.insert(start, compiled);
+        }
+    }
+}
+
+impl<T: Tape> Engine<T> for Tiered<'_, T> {
+    fn load(&mut self, program: &Program) {
+        self.instructions = program.to_vec();
+        self.instr_ptr = 0;
+        self.iterations.clear();
+        self.compiled.clear();
+        self.running = None;
+    }
+
+    fn run(&mut self, limits: RunLimits) -> Result<(), RuntimeError> {
+        self.machine.set_limits(limits);
+        check_program(&self.instructions)?;
+        while self.step()? {}
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<bool, RuntimeError> {
+        if self.running.is_none() && matches!(self.compiled.get(&self.instr_ptr), Some(Some(_))) {
+            // the optimized loop checks the condition again, so it replaces the plain loop right at its start
+            self.running = Some((self.instr_ptr, 0));
+        }
+
+        if let Some((start, loop_ptr)) = self.running {
+            let Some(Some(optimized)) = self.compiled.get(&start) else { unreachable!("only optimized loops are running") };
+            match self.machine.step(optimized, loop_ptr)? {
+                Some(next) => self.running = Some((start, next)),
+                None => {
+                    let Instruction::JmpZ(end) = self.instructions[start] else { unreachable!("loops start with a JmpZ") };
+                    self.instr_ptr = end + 1;
+                    self.running = None;
+                },
+            }
+            return Ok(true);
+        }
+
+        match self.machine.step(&self.instructions, self.instr_ptr)? {
+            Some(next) => {
+                if let Instruction::Jmp(start) = self.instructions[self.instr_ptr] {
+                    self.repeat(start);
+                }
+                self.instr_ptr = next;
+                Ok(true)
+            },
+            None => {
+                self.machine.finish()?;
+                Ok(false)
+            },
+        }
+    }
+
+    fn instr_ptr(&self) -> usize {
+        self.instr_ptr
+    }
+
+    fn next_instruction(&self) -> &Instruction {
+        match (self.running, self.compiled.get(&self.instr_ptr)) {
+            (Some((start, loop_ptr)), _) => match self.compiled.get(&start) {
+                Some(Some(optimized)) => &optimized[loop_ptr],
+                _ => unreachable!("only optimized loops are running"),
+            },
+            // the next step enters the optimized loop
+            (None, Some(Some(optimized))) => &optimized[0],
+            (None, _) => &self.instructions[self.instr_ptr],
+        }
+    }
+
+    fn machine(&mut self) -> &mut Machine<T> {
+        self.machine
+    }
+}
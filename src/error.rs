@@ -0,0 +1,55 @@
+use core::fmt::Display;
+
+use crate::ProgramError;
+use crate::compiler::ParseError;
+use crate::failure::Failure;
+use crate::vm::RuntimeError;
+
+/// Every error, that loading, compiling or running a program can produce
+#[derive(Debug)]
+pub enum BfError {
+    Program(ProgramError),
+    Parse(ParseError),
+    Runtime(RuntimeError),
+}
+
+impl BfError {
+    /// the failure class, which also decides the exit code of the command line tool
+    pub fn failure(&self) -> Failure {
+        match self {
+            BfError::Program(err) => err.failure(),
+            BfError::Parse(_) => Failure::Parse,
+            BfError::Runtime(err) => err.failure(),
+        }
+    }
+}
+
+impl Display for BfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BfError::Program(err) => write!(f, "{err}"),
+            BfError::Parse(err) => write!(f, "{err}"),
+            BfError::Runtime(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for BfError {}
+
+impl From<ProgramError> for BfError {
+    fn from(err: ProgramError) -> Self {
+        BfError::Program(err)
+    }
+}
+
+impl From<ParseError> for BfError {
+    fn from(err: ParseError) -> Self {
+        BfError::Parse(err)
+    }
+}
+
+impl From<RuntimeError> for BfError {
+    fn from(err: RuntimeError) -> Self {
+        BfError::Runtime(err)
+    }
+}
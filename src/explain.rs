@@ -0,0 +1,96 @@
+use std::io::{BufRead, BufReader, Write};
+use std::thread;
+use std::time::Duration;
+
+use crate::compiler::{Instruction, Program};
+use crate::engine::Engine;
+use crate::vm::{Machine, RuntimeError};
+
+/// Runs a program slowly and narrates every instruction in plain English, for `--explain`
+pub struct Explainer {
+    delay: Duration,
+    /// wait for enter at the start of every loop, instead of sleeping
+    pause_on_loops: bool,
+    /// the last instruction jumped back to the start of a loop
+    repeating: bool,
+}
+
+impl Explainer {
+    pub fn new(delay: Duration, pause_on_loops: bool) -> Self {
+        Explainer { delay, pause_on_loops, repeating: false }
+    }
+
+    /// run the program step by step on the engine, printing what each instruction did to stderr
+    pub fn run(&mut self, engine: &mut dyn Engine, program: &Program) -> Result<(), RuntimeError> {
+        engine.load(program);
+        loop {
+            let instr = engine.next_instruction().clone();
+            let at = program.span(engine.instr_ptr()).map(|pos| format!("{}:{}: ", pos.line, pos.col)).unwrap_or_default();
+            if !engine.step()? {
+                return Ok(());
+            }
+
+            // the program output has to be visible before the narration of what comes next
+            let _ = std::io::stdout().flush();
+            eprintln!("{at}{}", self.narrate(&instr, engine.machine()));
+
+            if self.pause_on_loops && matches!(instr, Instruction::JmpZ(_) | Instruction::If(_)) {
+                wait_for_enter();
+            } else {
+                thread::sleep(self.delay);
+            }
+            self.repeating = matches!(instr, Instruction::Jmp(_));
+        }
+    }
+
+    /// describe the instruction, that was just executed on the machine
+    fn narrate(&self, instr: &Instruction, machine: &Machine) -> String {
+        let (cell, value) = (machine.ptr(), machine.value());
+        match instr {
+            Instruction::MvLeft(1) => format!("move pointer left to cell {cell}"),
+            Instruction::MvLeft(times) => format!("move pointer left by {times} to cell {cell}"),
+            Instruction::MvRight(1) => format!("move pointer right to cell {cell}"),
+            Instruction::MvRight(times) => format!("move pointer right by {times} to cell {cell}"),
+            Instruction::Inc(1) => format!("increase cell {cell} to {value}"),
+            Instruction::Inc(times) => format!("increase cell {cell} by {times} to {value}"),
+            Instruction::Dec(1) => format!("decrease cell {cell} to {value}"),
+            Instruction::Dec(times) => format!("decrease cell {cell} by {times} to {value}"),
+            Instruction::Set(_) => format!("set cell {cell} to {value}"),
+            Instruction::MulAdd(updates) => {
+                let targets: Vec<String> = updates.iter()
+                    .map(|(offset, factor)| format!("times {factor} to cell {}", cell as isize + offset))
+                    .collect();
+                format!("add cell {cell} {}, then clear cell {cell}", targets.join(", "))
+            },
+            Instruction::Put => format!("print cell {cell} = {value} ({:?})", char::from(value)),
+            Instruction::PutStr(bytes) => format!("print {:?}", bytes.iter().map(|&byte| char::from(byte)).collect::<String>()),
+            Instruction::PutN { count, stride: 0 } => format!("print cell {cell} = {value} {count} times"),
+            Instruction::PutN { count, stride } => format!("print {count} cells {stride} apart, ending at cell {cell}"),
+            Instruction::GetN { count, stride: 0 } => format!("read {count} bytes into cell {cell}, it is now {value}"),
+            Instruction::GetN { count, stride } => format!("read {count} bytes into cells {stride} apart, ending at cell {cell} = {value}"),
+            Instruction::Get => format!("read a byte into cell {cell}, it is now {value}"),
+            Instruction::Rand => format!("store the random number {value} in cell {cell}"),
+            Instruction::NextTape(_) | Instruction::PrevTape(_) => format!("switch to tape {}, pointer at cell {cell}", machine.tape()),
+            Instruction::Jmp(_) => String::from("go back to the start of the loop"),
+            Instruction::JmpZ(_) => match (value, self.repeating) {
+                (0, true) => format!("leave the loop because cell {cell} = 0"),
+                (0, false) => format!("skip the loop because cell {cell} = 0"),
+                (_, true) => format!("loop repeats because cell {cell} = {value}"),
+                (_, false) => format!("enter the loop because cell {cell} = {value}"),
+            },
+            Instruction::If(_) if value == 0 => format!("skip the loop because cell {cell} = 0"),
+            Instruction::If(_) => format!("enter the loop once because cell {cell} = {value}"),
+            // the end of the program isn't narrated, only the end of a loop, that the tiered engine optimized
+            Instruction::Exit => String::from("leave the optimized loop"),
+        }
+    }
+}
+
+/// block until enter is pressed on the terminal, stdin might be the input of the program
+/// without a terminal device (e.g. under WASI) there is nothing to wait for
+fn wait_for_enter() {
+    if let Ok(tty) = std::fs::File::open("/dev/tty") {
+        eprint!("(press enter to continue)");
+        let _ = BufReader::new(tty).read_line(&mut String::new());
+    }
+}
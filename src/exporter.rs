@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::failure::Failure;
+
+/// upper bounds of the latency buckets in seconds
+const BUCKETS: [f64; 10] = [0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// observations per bucket, not cumulative
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(bucket) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    /// latency of the requests by method
+    requests: BTreeMap<&'static str, Histogram>,
+    executions: u64,
+    failures: BTreeMap<&'static str, u64>,
+    steps: u64,
+}
+
+/// Counters of a long running service, shared with the thread serving them
+#[derive(Debug, Clone, Default)]
+pub struct ServiceMetrics(Arc<Mutex<Registry>>);
+
+impl ServiceMetrics {
+    pub fn observe_request(&self, method: &'static str, latency: Duration) {
+        self.lock().requests.entry(method).or_default().observe(latency.as_secs_f64());
+    }
+
+    /// a program ran to its end, `failure` is set if it ended with an error
+    pub fn record_execution(&self, failure: Option<Failure>) {
+        let mut registry = self.lock();
+        registry.executions += 1;
+        if let Some(failure) = failure {
+            *registry.failures.entry(kind(failure)).or_default() += 1;
+        }
+    }
+
+    /// a request failed before anything ran, e.g. the program didn't compile
+    pub fn record_failure(&self, failure: Failure) {
+        *self.lock().failures.entry(kind(failure)).or_default() += 1;
+    }
+
+    pub fn add_steps(&self, steps: u64) {
+        self.lock().steps += steps;
+    }
+
+    /// the counters in the prometheus text format
+    pub fn render(&self) -> String {
+        let registry = self.lock();
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP bf_executions_total Programs, that ran to their end or failed while running.");
+        let _ = writeln!(out, "# TYPE bf_executions_total counter");
+        let _ = writeln!(out, "bf_executions_total {}", registry.executions);
+
+        let _ = writeln!(out, "# HELP bf_failures_total Failed compilations, runs and requests by kind.");
+        let _ = writeln!(out, "# TYPE bf_failures_total counter");
+        for failure in Failure::ALL {
+            let count = registry.failures.get(kind(failure)).copied().unwrap_or(0);
+            let _ = writeln!(out, "bf_failures_total{{kind=\"{}\"}} {count}", kind(failure));
+        }
+
+        let _ = writeln!(out, "# HELP bf_steps_total Instructions executed.");
+        let _ = writeln!(out, "# TYPE bf_steps_total counter");
+        let _ = writeln!(out, "bf_steps_total {}", registry.steps);
+
+        let _ = writeln!(out, "# HELP bf_request_duration_seconds Time to answer a request by method.");
+        let _ = writeln!(out, "# TYPE bf_request_duration_seconds histogram");
+        for (method, histogram) in &registry.requests {
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(out, "bf_request_duration_seconds_bucket{{method=\"{method}\",le=\"{bound}\"}} {cumulative}");
+            }
+            let _ = writeln!(out, "bf_request_duration_seconds_bucket{{method=\"{method}\",le=\"+Inf\"}} {}", histogram.count);
+            let _ = writeln!(out, "bf_request_duration_seconds_sum{{method=\"{method}\"}} {}", histogram.sum);
+            let _ = writeln!(out, "bf_request_duration_seconds_count{{method=\"{method}\"}} {}", histogram.count);
+        }
+        out
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Registry> {
+        // the counters stay usable, even if a thread panicked while holding the lock
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn kind(failure: Failure) -> &'static str {
+    match failure {
+        Failure::Usage => "usage",
+        Failure::Parse => "parse",
+        Failure::Runtime => "runtime",
+        Failure::Limit => "limit",
+        Failure::Io => "io",
+    }
+}
+
+/// Serve `GET /metrics` on a background thread, the listener is bound before this returns
+pub fn serve(addr: SocketAddr, metrics: ServiceMetrics) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local = listener.local_addr()?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // a broken scrape only affects that scrape
+            let _ = respond(stream, &metrics);
+        }
+    });
+    Ok(local)
+}
+
+fn respond(mut stream: TcpStream, metrics: &ServiceMetrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // the headers aren't needed, but have to be read before answering
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", String::from("only /metrics is served\n")),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
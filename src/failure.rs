@@ -0,0 +1,30 @@
+/// Classes of failures, each with its own exit code of the binary
+/// wrappers can branch on the exit code instead of parsing the error message on stderr
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Failure {
+    /// invalid command line arguments or project config
+    Usage,
+    /// the program couldn't be decoded or compiled
+    Parse,
+    /// the program failed while running, e.g. the pointer left the tape on either end
+    Runtime,
+    /// the program exceeded a configured limit, e.g. the steps or the timeout
+    Limit,
+    /// reading or writing a file, stdin or a connection failed, e.g. the program couldn't be read or downloaded
+    Io,
+}
+
+impl Failure {
+    /// every class, e.g. to report a counter for each of them
+    pub const ALL: [Failure; 5] = [Failure::Usage, Failure::Parse, Failure::Runtime, Failure::Limit, Failure::Io];
+
+    pub const fn exit_code(self) -> i32 {
+        match self {
+            Failure::Usage => 1,
+            Failure::Parse => 2,
+            Failure::Runtime => 3,
+            Failure::Limit => 4,
+            Failure::Io => 5,
+        }
+    }
+}
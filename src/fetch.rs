@@ -0,0 +1,33 @@
+use std::io::Read;
+use std::time::Duration;
+
+/// Programs larger than this are rejected, to not fill the memory with a bogus download
+const MAX_PROGRAM_SIZE: u64 = 16 * 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// download the program source from the given url
+pub fn fetch_program(url: &str) -> Result<String, String> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .build();
+    let response = agent.get(url)
+        .call()
+        .map_err(|err| format!("Request failed: {err}"))?;
+
+    // reject early if the server already announces a body that is too big
+    let announced = response.header("Content-Length").and_then(|len| len.parse::<u64>().ok());
+    if announced.is_some_and(|len| len > MAX_PROGRAM_SIZE) {
+        return Err(format!("Program at {url} is bigger than the limit of {MAX_PROGRAM_SIZE} bytes"));
+    }
+
+    let mut bytes = Vec::new();
+    response.into_reader()
+        .take(MAX_PROGRAM_SIZE + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|err| format!("Failed to download {url}: {err}"))?;
+    if bytes.len() as u64 > MAX_PROGRAM_SIZE {
+        return Err(format!("Program at {url} is bigger than the limit of {MAX_PROGRAM_SIZE} bytes"));
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
@@ -0,0 +1,18 @@
+use crate::compiler::{CompileOptions, ParseError, Program};
+use crate::syntax::SourceFile;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Stable digest of the commands of a program, comments and formatting don't change it
+/// the program has to compile, so only valid programs get a fingerprint
+pub fn fingerprint(source: &str, options: &CompileOptions) -> Result<u64, ParseError> {
+    Program::compile(source, options)?;
+    let commands = SourceFile::parse(source, options).commands();
+    Ok(fnv1a(commands.as_bytes()))
+}
+
+/// 64 bit FNV-1a, simple and identical on every platform
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME))
+}
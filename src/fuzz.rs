@@ -0,0 +1,283 @@
+use clap::ValueEnum;
+use core::fmt::Display;
+
+use crate::Config;
+use crate::compiler::{CompileOptions, Program};
+use crate::engine::{self as backend, Engine as _};
+use crate::vm::{Machine, Rng};
+
+/// Rightmost cell a generated program may use
+const MAX_PTR: usize = 16;
+/// Longest run of `+`, `-`, `>` or `<`, long enough to wrap a cell around twice
+const MAX_RUN: usize = 600;
+/// Deepest loop nesting of a generated program
+const MAX_DEPTH: usize = 3;
+const MAX_LEN: usize = 40;
+const MAX_INPUT: usize = 8;
+
+/// Ways to run a program, whose results have to be identical
+#[derive(Debug, PartialEq, Eq, Clone, Copy, ValueEnum)]
+pub enum Engine {
+    /// the plain interpreter
+    Interp,
+    /// the interpreter with all optimizer passes
+    Optimized,
+    /// the interpreter, that optimizes loops while they run, after their second repetition
+    Tiered,
+}
+
+impl Display for Engine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self.to_possible_value().expect("no engine is skipped");
+        write!(f, "{}", value.get_name())
+    }
+}
+
+/// What a program did: its output and the error it stopped with
+#[derive(Debug, PartialEq, Clone)]
+pub struct Outcome {
+    pub output: Vec<u8>,
+    pub error: Option<String>,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "output \"{}\"", self.output.escape_ascii())?;
+        if let Some(error) = &self.error {
+            write!(f, ", {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A program and input, on which the engines disagree, already minimized
+pub struct Divergence {
+    pub iteration: u64,
+    pub program: String,
+    pub input: Vec<u8>,
+    pub outcomes: Vec<(Engine, Outcome)>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "engines diverged in iteration {}", self.iteration)?;
+        writeln!(f, "program: {}", self.program)?;
+        write!(f, "input: \"{}\"", self.input.escape_ascii())?;
+        for (engine, outcome) in &self.outcomes {
+            write!(f, "\n{engine}: {outcome}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Differential fuzzer, that runs random programs with random input on several engines
+pub struct Fuzzer<'a> {
+    cnfg: &'a Config,
+    engines: Vec<Engine>,
+    max_steps: u64,
+    rng: Rng,
+    /// runs, that were skipped, because an engine hit the step limit
+    pub skipped: u64,
+}
+
+impl<'a> Fuzzer<'a> {
+    /// the machines are created from `cnfg`, so options like the cell size or eof policy apply
+    pub fn new(cnfg: &'a Config, engines: Vec<Engine>, max_steps: u64, seed: u64) -> Self {
+        Fuzzer { cnfg, engines, max_steps, rng: Rng::new(seed), skipped: 0 }
+    }
+
+    /// run up to `iterations` random programs, stops at the first divergence
+    pub fn run(&mut self, iterations: u64) -> Option<Divergence> {
+        for iteration in 1..=iterations {
+            let len = self.below(MAX_LEN) + 1;
+            let mut program = String::new();
+            self.block(&mut program, &mut 0, 0, len);
+            let input: Vec<u8> = (0..self.below(MAX_INPUT + 1)).map(|_| self.rng.next_byte()).collect();
+
+            match self.outcomes(&program, &input) {
+                None => self.skipped += 1,
+                Some(outcomes) if diverged(&outcomes) => {
+                    let (program, input) = self.minimize(program, input);
+                    let outcomes = self.outcomes(&program, &input).expect("minimized case should still run");
+                    return Some(Divergence { iteration, program, input, outcomes });
+                },
+                Some(_) => {},
+            }
+        }
+        None
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (usize::from(self.rng.next_byte()) << 8 | usize::from(self.rng.next_byte())) % bound
+    }
+
+    /// length of a run of the same command, mostly a single one
+    fn run_len(&mut self, max: usize) -> usize {
+        match self.below(4) {
+            0 => self.below(max) + 1,
+            _ => 1,
+        }
+    }
+
+    /// append random commands, the pointer only leaves `0..=MAX_PTR` (or the tape) right before the program fails
+    /// loops move the pointer back to where they started, so its position is always known
+    fn block(&mut self, program: &mut String, ptr: &mut usize, depth: usize, len: usize) {
+        let start = *ptr;
+        let last = MAX_PTR.min(self.cnfg.cell_sz - 1);
+        for _ in 0..len {
+            match self.below(10) {
+                0 | 1 => program.push_str(&"+".repeat(self.run_len(MAX_RUN))),
+                2 | 3 => program.push_str(&"-".repeat(self.run_len(MAX_RUN))),
+                4 | 5 if self.below(40) == 0 => {
+                    // moves past an end of the tape, to compare how the engines fail
+                    if last < MAX_PTR && self.below(2) == 0 {
+                        program.push_str(&">".repeat(last - *ptr + 1 + self.below(3)));
+                    } else {
+                        program.push_str(&"<".repeat(*ptr + 1 + self.below(3)));
+                    }
+                },
+                4 if *ptr < last => {
+                    let by = self.run_len(last - *ptr);
+                    program.push_str(&">".repeat(by));
+                    *ptr += by;
+                },
+                5 if *ptr > 0 => {
+                    let by = self.run_len(*ptr);
+                    program.push_str(&"<".repeat(by));
+                    *ptr -= by;
+                },
+                6 => program.push('.'),
+                7 => program.push(','),
+                8 | 9 if depth < MAX_DEPTH => {
+                    // counting the condition down in a cell, the body starts beside, makes it likely that the loop ends
+                    program.push_str("[-");
+                    let len = self.below(8) + 1;
+                    let body_start = if *ptr < last { program.push('>'); *ptr + 1 } else { *ptr };
+                    self.block(program, &mut { body_start }, depth + 1, len);
+                    if body_start > *ptr { program.push('<'); }
+                    program.push(']');
+                },
+                _ => {},
+            }
+        }
+
+        if depth > 0 {
+            while *ptr > start { program.push('<'); *ptr -= 1; }
+            while *ptr < start { program.push('>'); *ptr += 1; }
+        }
+    }
+
+    /// the outcome of every engine, `None` if one of them hit the step limit
+    fn outcomes(&self, program: &str, input: &[u8]) -> Option<Vec<(Engine, Outcome)>> {
+        self.engines.iter()
+            .map(|engine| self.execute(*engine, program, input).map(|outcome| (*engine, outcome)))
+            .collect()
+    }
+
+    fn execute(&self, engine: Engine, program: &str, input: &[u8]) -> Option<Outcome> {
+        let options = CompileOptions { optimize: engine == Engine::Optimized, ..self.cnfg.compile_options() };
+        let program = match Program::compile(program, &options) {
+            Ok(program) => program,
+            Err(err) => return Some(Outcome { output: Vec::new(), error: Some(err.get_error_msg(program)) }),
+        };
+
+        let mut machine = Machine::new(self.cnfg);
+        machine.capture_output();
+        machine.close_stdin();
+        machine.feed_input(input);
+
+        let mut instr_ptr = 0;
+        let mut steps = 0;
+        let result = if engine == Engine::Tiered {
+            // generated programs are short, so the loops have to get hot early to test the switch
+            let mut tiered = backend::Tiered::new(&mut machine).hot_after(2);
+            tiered.load(&program);
+            loop {
+                match tiered.step() {
+                    Ok(true) => {},
+                    Ok(false) => break Ok(()),
+                    Err(err) => break Err(err),
+                }
+                steps += 1;
+                if steps > self.max_steps {
+                    return None;
+                }
+            }
+        } else {
+            loop {
+                match machine.step(&program, instr_ptr) {
+                    Ok(Some(next)) => instr_ptr = next,
+                    Ok(None) => break machine.finish(),
+                    Err(err) => break Err(err),
+                }
+                steps += 1;
+                if steps > self.max_steps {
+                    return None;
+                }
+            }
+        };
+        Some(Outcome { output: machine.take_output(), error: result.err().map(|err| err.to_string()) })
+    }
+
+    fn diverges(&self, program: &str, input: &[u8]) -> bool {
+        self.outcomes(program, input).is_some_and(|outcomes| diverged(&outcomes))
+    }
+
+    /// remove commands, loops and input bytes, as long as the engines still disagree
+    fn minimize(&self, mut program: String, mut input: Vec<u8>) -> (String, Vec<u8>) {
+        'shrink: loop {
+            for candidate in smaller_programs(&program) {
+                if self.diverges(&candidate, &input) {
+                    program = candidate;
+                    continue 'shrink;
+                }
+            }
+            for index in 0..input.len() {
+                let mut candidate = input.clone();
+                candidate.remove(index);
+                if self.diverges(&program, &candidate) {
+                    input = candidate;
+                    continue 'shrink;
+                }
+            }
+            return (program, input);
+        }
+    }
+}
+
+fn diverged(outcomes: &[(Engine, Outcome)]) -> bool {
+    outcomes.windows(2).any(|pair| pair[0].1 != pair[1].1)
+}
+
+/// every program with a single command, bracket pair or whole loop removed
+fn smaller_programs(program: &str) -> Vec<String> {
+    let bytes = program.as_bytes();
+    let mut candidates = Vec::new();
+    for (index, byte) in bytes.iter().enumerate() {
+        match byte {
+            b'[' => {
+                let Some(end) = matching_bracket(bytes, index) else { continue; };
+                candidates.push(format!("{}{}", &program[..index], &program[end + 1..]));
+                candidates.push(format!("{}{}{}", &program[..index], &program[index + 1..end], &program[end + 1..]));
+            },
+            b']' => {},
+            _ => candidates.push(format!("{}{}", &program[..index], &program[index + 1..])),
+        }
+    }
+    candidates
+}
+
+fn matching_bracket(bytes: &[u8], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (index, byte) in bytes.iter().enumerate().skip(open) {
+        match byte {
+            b'[' => depth += 1,
+            b']' => depth -= 1,
+            _ => continue,
+        }
+        if depth == 0 {
+            return Some(index);
+        }
+    }
+    None
+}
@@ -0,0 +1,78 @@
+/// largest loop counter and body tried for the multiplication encoding
+const MAX_FACTOR: usize = 64;
+/// the generated program is wrapped after this many commands
+const LINE_WIDTH: usize = 80;
+
+/// A program, that prints exactly the given bytes
+/// every byte is built from the previous one in the same cell, either by adding or subtracting directly,
+/// with a multiplication loop on the cell to the right, or after clearing the cell with `[-]`, whatever is shortest
+pub fn data_program(bytes: &[u8]) -> String {
+    let table = delta_table();
+
+    let mut program = String::new();
+    let mut value = 0u8;
+    for &byte in bytes {
+        let relative = &table[byte.wrapping_sub(value) as usize];
+        let cleared = &table[byte as usize];
+        if value != 0 && cleared.len() + 3 < relative.len() {
+            program.push_str("[-]");
+            program.push_str(cleared);
+        } else {
+            program.push_str(relative);
+        }
+        program.push('.');
+        value = byte;
+    }
+
+    let chars: Vec<char> = program.chars().collect();
+    let mut wrapped: String = chars.chunks(LINE_WIDTH).map(|line| line.iter().collect::<String>() + "\n").collect();
+    if wrapped.is_empty() {
+        wrapped.push('\n');
+    }
+    wrapped
+}
+
+/// shortest commands, that add every value from 0 to 255 to the current cell (wrapping)
+/// the cell to the right is used as loop counter and left at zero
+fn delta_table() -> Vec<String> {
+    let direct = |delta: u8| -> String {
+        if delta <= 128 { "+".repeat(delta as usize) } else { "-".repeat(256 - delta as usize) }
+    };
+
+    let mut table: Vec<String> = (0..=255).map(direct).collect();
+    for counter in 2..=MAX_FACTOR {
+        for step in 2..=MAX_FACTOR {
+            for body in ['+', '-'] {
+                let product = (counter * step % 256) as u8;
+                let reached = if body == '+' { product } else { product.wrapping_neg() };
+                let multiplication = format!(">{}[<{}>-]<", "+".repeat(counter), body.to_string().repeat(step));
+                for (delta, best) in table.iter_mut().enumerate() {
+                    let rest = direct((delta as u8).wrapping_sub(reached));
+                    if multiplication.len() + rest.len() < best.len() {
+                        *best = multiplication.clone() + &rest;
+                    }
+                }
+            }
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler::Program, vm::MachineBuilder};
+
+    #[test]
+    fn data_program_prints_the_exact_bytes() {
+        let bytes: Vec<u8> = [0, 1, 0xff, 0x80, b'\n', 0x7f].into_iter().chain(0..=255).chain((0..=255).rev()).collect();
+        for optimize in [false, true] {
+            let program = Program::from_str(&data_program(&bytes), optimize).unwrap();
+            let mut machine = MachineBuilder::new().build();
+            machine.close_stdin();
+            machine.capture_output();
+            machine.run(&program).unwrap();
+            assert_eq!(machine.take_output(), bytes, "optimize: {optimize}");
+        }
+    }
+}
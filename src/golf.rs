@@ -0,0 +1,101 @@
+use core::fmt::Display;
+
+use crate::compiler::{CompileOptions, Extension};
+use crate::syntax::SourceFile;
+
+/// Code golf score of a program, printed by the `golf` subcommand
+#[derive(Debug, PartialEq, Clone)]
+pub struct GolfReport {
+    pub source_bytes: usize,
+    pub commands: usize,
+    /// commands, that cancel each other out, like `+-` or `<>`
+    pub cancellation: usize,
+    /// commands in loops at the start of the program or right after another loop, which never run
+    pub dead_loops: usize,
+    /// pointer moves and arithmetic after the last output or input
+    pub trailing: usize,
+}
+
+impl GolfReport {
+    /// score the source, in strict mode only the eight standard commands count
+    pub fn new(source: &str, strict: bool) -> Self {
+        let extensions = if strict { Vec::new() } else { vec![Extension::MultiTape, Extension::Rand] };
+        let commands = SourceFile::parse(source, &CompileOptions { extensions, ..Default::default() }).commands();
+        let commands: Vec<char> = commands.chars().collect();
+
+        GolfReport {
+            source_bytes: source.len(),
+            commands: commands.len(),
+            cancellation: cancellation(&commands),
+            dead_loops: dead_loops(&commands),
+            trailing: trailing(&commands),
+        }
+    }
+}
+
+/// commands removable from runs of `+`/`-` and `<`/`>`
+/// a run of arithmetic only needs the shorter way around the 256 values of a cell
+fn cancellation(commands: &[char]) -> usize {
+    let mut removable = 0;
+    for run in commands.chunk_by(|a, b| group(*a).is_some() && group(*a) == group(*b)) {
+        let Some(group) = group(run[0]) else { continue; };
+        let net: isize = run.iter().map(|ch| if *ch == group.0 { 1 } else { -1 }).sum();
+        let needed = if group.0 == '+' {
+            let net = net.rem_euclid(256) as usize;
+            net.min(256 - net)
+        } else {
+            net.unsigned_abs()
+        };
+        removable += run.len() - needed;
+    }
+    removable
+}
+
+/// the commands, that can cancel each other out with `ch`
+fn group(ch: char) -> Option<(char, char)> {
+    match ch {
+        '+' | '-' => Some(('+', '-')),
+        '<' | '>' => Some(('>', '<')),
+        _ => None,
+    }
+}
+
+/// the cell is zero at the start and after a loop, so a loop there is skipped
+fn dead_loops(commands: &[char]) -> usize {
+    let mut removable = 0;
+    let mut i = 0;
+    while i < commands.len() {
+        if commands[i] == '[' && (i == 0 || commands[i - 1] == ']') {
+            let mut depth = 0;
+            let start = i;
+            while i < commands.len() {
+                match commands[i] {
+                    '[' => depth += 1,
+                    ']' => depth -= 1,
+                    _ => {},
+                }
+                i += 1;
+                if depth == 0 { break; }
+            }
+            removable += i - start;
+        } else {
+            i += 1;
+        }
+    }
+    removable
+}
+
+fn trailing(commands: &[char]) -> usize {
+    commands.iter().rev().take_while(|ch| matches!(ch, '+' | '-' | '<' | '>')).count()
+}
+
+impl Display for GolfReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} bytes of source", self.source_bytes)?;
+        writeln!(f, "{} commands", self.commands)?;
+        writeln!(f, "{} bytes of comments and whitespace", self.source_bytes - self.commands)?;
+        writeln!(f, "{} bytes removable via cancellation", self.cancellation)?;
+        writeln!(f, "{} bytes removable in loops that never run", self.dead_loops)?;
+        write!(f, "{} bytes removable at the end, after the last input or output", self.trailing)
+    }
+}
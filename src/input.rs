@@ -0,0 +1,154 @@
+use clap::ValueEnum;
+use std::collections::VecDeque;
+use std::io::{IsTerminal, Read, Write};
+
+use crate::script::InputScript;
+
+/// What `,` stores in the current cell once the input ended
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum EofPolicy {
+    /// store 0
+    #[default]
+    Zero,
+    /// store 255 (-1)
+    Max,
+    /// leave the cell unchanged
+    Unchanged,
+}
+
+/// Bytes given on the command line, already unescaped
+#[derive(Debug, PartialEq, Clone)]
+pub struct InputPrefix(pub Vec<u8>);
+
+/// parse a string with escape sequences (`\n`, `\t`, `\r`, `\0`, `\\`, `\"`, `\xHH`) into raw bytes
+/// used as clap value parser, so invalid escapes are reported as usage errors
+pub fn parse_escaped(str: &str) -> Result<InputPrefix, String> {
+    let mut bytes = Vec::with_capacity(str.len());
+    let mut chars = str.chars().enumerate();
+
+    while let Some((pos, ch)) = chars.next() {
+        if ch != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        let byte = match chars.next() {
+            Some((_, 'n')) => b'\n',
+            Some((_, 't')) => b'\t',
+            Some((_, 'r')) => b'\r',
+            Some((_, '0')) => b'\0',
+            Some((_, '\\')) => b'\\',
+            Some((_, '"')) => b'"',
+            Some((_, 'x')) => {
+                let hex: String = chars.by_ref().take(2).map(|(_, ch)| ch).collect();
+                u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .ok_or_else(|| format!("invalid hex escape '\\x{hex}' at {pos}, expected two hex digits"))?
+            },
+            Some((_, other)) => return Err(format!("unknown escape sequence '\\{other}' at {pos}")),
+            None => return Err(String::from("trailing backslash at end of input")),
+        };
+        bytes.push(byte);
+    }
+
+    Ok(InputPrefix(bytes))
+}
+
+/// Why the next byte couldn't be read, the end of the input isn't an error
+#[derive(Debug)]
+pub enum InputError {
+    /// the input script didn't allow reading here
+    Script(String),
+    /// reading stdin failed
+    Io(std::io::Error),
+}
+
+/// Input source for the `,` instruction
+/// bytes from the prefix are consumed first, afterwards the input script or stdin is read
+pub struct Input {
+    prefix: VecDeque<u8>,
+    script: Option<InputScript>,
+    prompt: Option<String>,
+    line_start: bool,
+    stdin: bool,
+}
+
+impl Input {
+    pub fn new(prefix: Vec<u8>) -> Input {
+        Input { prefix: prefix.into(), script: None, prompt: None, line_start: true, stdin: true }
+    }
+
+    /// print the prompt to stderr, whenever `,` waits for a new line from a terminal
+    /// it is never shown if stdin isn't a terminal
+    pub fn set_prompt(&mut self, prompt: String) {
+        self.prompt = Some(prompt);
+    }
+
+    /// never read stdin, the input ends with the prefix (or script)
+    pub fn close_stdin(&mut self) {
+        self.stdin = false;
+    }
+
+    /// read from the script instead of stdin
+    pub fn set_script(&mut self, script: InputScript) {
+        self.script = Some(script);
+    }
+
+    /// hook for the write path of the machine
+    pub fn observe_output(&mut self, byte: u8) {
+        if let Some(script) = &mut self.script {
+            script.observe_output(byte);
+        }
+    }
+
+    /// fails if an input script still waits for output
+    pub fn check_finished(&self) -> Result<(), String> {
+        self.script.as_ref().map_or(Ok(()), InputScript::check_finished)
+    }
+
+    /// append bytes to the prefix, so they are read before stdin
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.prefix.extend(bytes);
+    }
+
+    /// read the next byte, `None` if the input ended
+    pub fn read_byte(&mut self, steps: u64) -> Result<Option<u8>, InputError> {
+        if let Some(byte) = self.prefix.pop_front() {
+            return Ok(Some(byte));
+        }
+        if let Some(script) = &mut self.script {
+            return script.read_byte(steps).map_err(InputError::Script);
+        }
+        if !self.stdin {
+            return Ok(None);
+        }
+
+        let mut stdin = std::io::stdin();
+        // a terminal delivers whole lines, so only the first byte of a line blocks
+        if self.line_start && stdin.is_terminal() {
+            if let Some(prompt) = &self.prompt {
+                let _ = std::io::stdout().flush();
+                eprint!("{prompt}");
+            }
+        }
+
+        // stdin is buffered internally, so reading it a byte at a time is cheap
+        // interrupted reads are retried, any other error is reported instead of being taken as the end
+        // the windows console ends lines with `\r\n`, programs get the `\n` they would get on unix
+        let crlf = crate::console::crlf_input();
+        let mut buf = [0];
+        let byte = loop {
+            match stdin.read(&mut buf) {
+                Ok(0) => break None,
+                Ok(_) if crlf && buf[0] == b'\r' => continue,
+                Ok(_) => break Some(buf[0]),
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(InputError::Io(err)),
+            }
+        };
+        self.line_start = byte == Some(b'\n');
+        Ok(byte)
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use zeromq::{PubSocket, RepSocket, RouterSocket, Socket, SocketRecv, SocketSend, ZmqMessage};
+
+use crate::compiler::{Instruction, Program};
+use crate::vm::{Machine, MachineBuilder, RuntimeError};
+use crate::Config;
+
+/// separates the routing identities from the message
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+const PROTOCOL_VERSION: &str = "5.3";
+/// output is published at every newline, or at the latest after this many steps
+const STREAM_INTERVAL: u64 = 1 << 16;
+
+/// The connection file, that jupyter writes for every kernel it starts
+#[derive(Deserialize)]
+struct Connection {
+    transport: String,
+    ip: String,
+    shell_port: u16,
+    iopub_port: u16,
+    stdin_port: u16,
+    control_port: u16,
+    hb_port: u16,
+    key: String,
+    signature_scheme: String,
+}
+
+/// A decoded message of the jupyter wire protocol
+struct Message {
+    identities: Vec<Bytes>,
+    header: Value,
+    content: Value,
+}
+
+/// The sockets and the persistent machine of a running kernel
+struct Kernel<'a> {
+    key: Vec<u8>,
+    session: String,
+    sent: u64,
+    iopub: PubSocket,
+    control: RouterSocket,
+    /// control requests, that arrived while a cell ran, other than `interrupt_request`
+    queued: VecDeque<Message>,
+    cnfg: &'a Config,
+    machine: Machine,
+    execution_count: u64,
+}
+
+/// Serve a jupyter kernel on the ports of the connection file until a shutdown request
+/// every cell runs on the same machine, so the tape is kept between cells
+pub fn run_kernel(cnfg: &Config, connection_file: &Path) -> Result<(), String> {
+    let connection = std::fs::read_to_string(connection_file)
+        .map_err(|err| format!("Error while reading {}:\n{err}", connection_file.display()))?;
+    let connection: Connection = serde_json::from_str(&connection).map_err(|err| format!("Invalid connection file: {err}"))?;
+    if !connection.key.is_empty() && connection.signature_scheme != "hmac-sha256" {
+        return Err(format!("Unsupported signature scheme {}", connection.signature_scheme));
+    }
+
+    // the heartbeat has to be answered while a cell runs, so it gets a worker of its own
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .map_err(|err| err.to_string())?;
+    runtime.block_on(serve(cnfg, connection)).map_err(|err| format!("Kernel error: {err}"))
+}
+
+async fn serve(cnfg: &Config, connection: Connection) -> zeromq::ZmqResult<()> {
+    let endpoint = |port: u16| format!("{}://{}:{port}", connection.transport, connection.ip);
+    let mut shell = RouterSocket::new();
+    shell.bind(&endpoint(connection.shell_port)).await?;
+    let mut control = RouterSocket::new();
+    control.bind(&endpoint(connection.control_port)).await?;
+    // `,` never reads from the frontend, but the socket has to exist
+    let mut stdin = RouterSocket::new();
+    stdin.bind(&endpoint(connection.stdin_port)).await?;
+    let mut iopub = PubSocket::new();
+    iopub.bind(&endpoint(connection.iopub_port)).await?;
+    let mut heartbeat = RepSocket::new();
+    heartbeat.bind(&endpoint(connection.hb_port)).await?;
+
+    tokio::spawn(async move {
+        while let Ok(ping) = heartbeat.recv().await {
+            if heartbeat.send(ping).await.is_err() { break; }
+        }
+    });
+
+    let session = format!("{:x}", SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_nanos()).unwrap_or(0));
+    let machine = new_machine(cnfg);
+    let mut kernel = Kernel {
+        key: connection.key.into_bytes(),
+        session,
+        sent: 0,
+        iopub,
+        control,
+        queued: VecDeque::new(),
+        cnfg,
+        machine,
+        execution_count: 0,
+    };
+
+    loop {
+        let (request, from_control) = match kernel.queued.pop_front() {
+            Some(request) => (request, true),
+            None => {
+                let (raw, from_control) = tokio::select! {
+                    raw = shell.recv() => (raw?, false),
+                    raw = kernel.control.recv() => (raw?, true),
+                };
+                let Some(request) = kernel.decode(raw) else { continue; };
+                (request, from_control)
+            },
+        };
+        if !kernel.handle(&mut shell, from_control, request).await? {
+            return Ok(());
+        }
+    }
+}
+
+fn new_machine(cnfg: &Config) -> Machine {
+    let mut machine = MachineBuilder::from_config(cnfg).build();
+    machine.close_stdin();
+    machine.capture_output();
+    machine
+}
+
+impl Kernel<'_> {
+    /// answer a request on the shell or control socket, `false` once the kernel should shut down
+    async fn handle(&mut self, shell: &mut RouterSocket, from_control: bool, request: Message) -> zeromq::ZmqResult<bool> {
+        let msg_type = request.header["msg_type"].as_str().unwrap_or_default().to_string();
+        self.publish(&request, "status", json!({ "execution_state": "busy" })).await?;
+
+        let reply = match msg_type.as_str() {
+            "kernel_info_request" => json!({
+                "status": "ok",
+                "protocol_version": PROTOCOL_VERSION,
+                "implementation": "bf-interpreter",
+                "implementation_version": env!("CARGO_PKG_VERSION"),
+                "language_info": { "name": "brainfuck", "mimetype": "text/x-brainfuck", "file_extension": ".bf" },
+                "banner": "Brainfuck, cells keep their values between cells. %%reset clears the tape, %%tape shows it.",
+            }),
+            "execute_request" => self.execute(&request).await?,
+            "is_complete_request" => {
+                let code = request.content["code"].as_str().unwrap_or_default();
+                let open = code.matches('[').count() > code.matches(']').count();
+                json!({ "status": if open { "incomplete" } else { "complete" }, "indent": "" })
+            },
+            "comm_info_request" => json!({ "status": "ok", "comms": {} }),
+            "history_request" => json!({ "status": "ok", "history": [] }),
+            // nothing runs, a running cell checks for interrupts itself
+            "interrupt_request" => json!({ "status": "ok" }),
+            "shutdown_request" => {
+                let restart = request.content["restart"].as_bool().unwrap_or(false);
+                self.reply(shell, from_control, &request, "shutdown_reply", json!({ "status": "ok", "restart": restart })).await?;
+                self.publish(&request, "status", json!({ "execution_state": "idle" })).await?;
+                return Ok(false);
+            },
+            _ => {
+                self.publish(&request, "status", json!({ "execution_state": "idle" })).await?;
+                return Ok(true);
+            },
+        };
+        let reply_type = msg_type.replace("_request", "_reply");
+        self.reply(shell, from_control, &request, &reply_type, reply).await?;
+        self.publish(&request, "status", json!({ "execution_state": "idle" })).await?;
+        Ok(true)
+    }
+
+    /// run a cell, after a line `%%reset` it starts with a fresh machine, `%%tape` prints the tape afterwards
+    async fn execute(&mut self, request: &Message) -> zeromq::ZmqResult<Value> {
+        let code = request.content["code"].as_str().unwrap_or_default().to_string();
+        if !request.content["silent"].as_bool().unwrap_or(false) {
+            self.execution_count += 1;
+        }
+        let count = self.execution_count;
+        self.publish(request, "execute_input", json!({ "code": code, "execution_count": count })).await?;
+
+        // magics are the leading lines starting with `%%`
+        let mut body = code.as_str();
+        let mut show_tape = false;
+        while let Some(magic) = body.trim_start().strip_prefix("%%") {
+            let (line, rest) = magic.split_once('\n').unwrap_or((magic, ""));
+            match line.trim() {
+                "reset" => self.machine = new_machine(self.cnfg),
+                "tape" => show_tape = true,
+                other => return self.error(request, "UsageError", format!("unknown magic %%{other}, use %%reset or %%tape")).await,
+            }
+            body = rest;
+        }
+
+        if !body.trim().is_empty() {
+            let program = match Program::compile(body, &self.cnfg.compile_options()) {
+                Ok(program) => program,
+                Err(err) => {
+                    let message = err.render(self.cnfg.error_style, "cell", body);
+                    return self.error(request, "ParseError", message).await;
+                },
+            };
+            if let Err(err) = self.run(request, &program).await {
+                return self.error(request, "RuntimeError", err).await;
+            }
+        }
+        if show_tape {
+            let dump = self.machine.dump_nonzero();
+            self.publish(request, "stream", json!({ "name": "stdout", "text": format!("{dump}\n") })).await?;
+        }
+        Ok(json!({ "status": "ok", "execution_count": count, "user_expressions": {}, "payload": [] }))
+    }
+
+    /// run the program on the machine, publishing the output while it runs
+    /// between two stretches of steps the control socket is checked for an interrupt
+    async fn run(&mut self, request: &Message, program: &Program) -> Result<(), String> {
+        let mut instr_ptr = 0;
+        let mut output = Vec::new();
+        loop {
+            let next = tokio::task::block_in_place(|| {
+                let mut steps = 0u64;
+                let mut next = self.machine.step(program, instr_ptr);
+                while let Ok(Some(ip)) = next {
+                    steps += 1;
+                    if matches!(program.get(instr_ptr), Some(Instruction::Put | Instruction::PutStr(_) | Instruction::PutN { .. })) {
+                        let printed = self.machine.take_output();
+                        output.extend_from_slice(&printed);
+                        if printed.contains(&b'\n') {
+                            break;
+                        }
+                    }
+                    if steps >= STREAM_INTERVAL {
+                        break;
+                    }
+                    instr_ptr = ip;
+                    next = self.machine.step(program, instr_ptr);
+                }
+                next
+            });
+            output.extend(self.machine.take_output());
+            if !output.is_empty() {
+                let text: String = std::mem::take(&mut output).into_iter().map(char::from).collect();
+                self.publish(request, "stream", json!({ "name": "stdout", "text": text })).await.map_err(|err| err.to_string())?;
+            }
+            match next {
+                Ok(Some(ip)) => instr_ptr = ip,
+                Ok(None) => return self.machine.finish().map_err(|err| err.to_string()),
+                Err(err) => return Err(err.to_string()),
+            }
+            if self.interrupted().await.map_err(|err| err.to_string())? {
+                return Err(RuntimeError::Cancelled(Box::new(self.machine.snapshot())).to_string());
+            }
+        }
+    }
+
+    /// true if an `interrupt_request` arrived on the control socket, which is answered right away
+    /// the other requests, that already arrived, are queued until the cell finished
+    async fn interrupted(&mut self) -> zeromq::ZmqResult<bool> {
+        let mut interrupted = false;
+        loop {
+            let raw = tokio::select! {
+                biased;
+                raw = self.control.recv() => raw?,
+                () = std::future::ready(()) => return Ok(interrupted),
+            };
+            let Some(request) = self.decode(raw) else { continue; };
+            if request.header["msg_type"] == "interrupt_request" {
+                let frames = self.encode(request.identities.clone(), &request, "interrupt_reply", json!({ "status": "ok" }));
+                self.control.send(frames).await?;
+                interrupted = true;
+            } else {
+                self.queued.push_back(request);
+            }
+        }
+    }
+
+    async fn error(&mut self, request: &Message, ename: &str, evalue: String) -> zeromq::ZmqResult<Value> {
+        let content = json!({ "ename": ename, "evalue": evalue, "traceback": [evalue] });
+        self.publish(request, "error", content.clone()).await?;
+        let mut reply = content;
+        reply["status"] = json!("error");
+        reply["execution_count"] = json!(self.execution_count);
+        Ok(reply)
+    }
+
+    async fn reply(&mut self, shell: &mut RouterSocket, to_control: bool, parent: &Message, msg_type: &str, content: Value) -> zeromq::ZmqResult<()> {
+        let frames = self.encode(parent.identities.clone(), parent, msg_type, content);
+        if to_control { self.control.send(frames).await } else { shell.send(frames).await }
+    }
+
+    async fn publish(&mut self, parent: &Message, msg_type: &str, content: Value) -> zeromq::ZmqResult<()> {
+        let frames = self.encode(vec![Bytes::from(msg_type.to_string())], parent, msg_type, content);
+        self.iopub.send(frames).await
+    }
+
+    fn encode(&mut self, identities: Vec<Bytes>, parent: &Message, msg_type: &str, content: Value) -> ZmqMessage {
+        self.sent += 1;
+        let header = json!({
+            "msg_id": format!("{}-{}", self.session, self.sent),
+            "session": self.session,
+            "username": "kernel",
+            "date": iso_date(SystemTime::now()),
+            "msg_type": msg_type,
+            "version": PROTOCOL_VERSION,
+        });
+        let parts = [header.to_string(), parent.header.to_string(), String::from("{}"), content.to_string()];
+        let signature = self.sign(&parts);
+
+        let mut frames = identities;
+        frames.push(Bytes::from_static(DELIMITER));
+        frames.push(Bytes::from(signature));
+        frames.extend(parts.into_iter().map(Bytes::from));
+        ZmqMessage::try_from(frames).expect("a message always has frames")
+    }
+
+    /// split the frames and check the signature, invalid messages are dropped
+    fn decode(&self, raw: ZmqMessage) -> Option<Message> {
+        let frames = raw.into_vec();
+        let delimiter = frames.iter().position(|frame| frame.as_ref() == DELIMITER)?;
+        let (identities, rest) = frames.split_at(delimiter);
+        let [_, signature, header, parent, metadata, content, ..] = rest else { return None; };
+        let parts: Vec<String> = [header, parent, metadata, content].iter()
+            .map(|frame| String::from_utf8_lossy(frame).into_owned())
+            .collect();
+        if self.sign(&parts).as_bytes() != signature.as_ref() {
+            return None;
+        }
+        Some(Message {
+            identities: identities.to_vec(),
+            header: serde_json::from_str(&parts[0]).ok()?,
+            content: serde_json::from_str(&parts[3]).ok()?,
+        })
+    }
+
+    /// hex encoded hmac of the parts, empty if the connection has no key
+    fn sign(&self, parts: &[String]) -> String {
+        if self.key.is_empty() {
+            return String::new();
+        }
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("hmac accepts keys of any length");
+        for part in parts {
+            mac.update(part.as_bytes());
+        }
+        mac.finalize().into_bytes().iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+}
+
+/// ISO 8601 date in UTC, as the protocol expects it in the header
+fn iso_date(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    // days to year, month and day in the proleptic gregorian calendar
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:06}Z",
+        secs / 3600 % 24, secs / 60 % 60, secs % 60, since_epoch.subsec_micros()
+    )
+}
+
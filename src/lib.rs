@@ -1,14 +1,97 @@
-use clap::Parser;
+//! Brainfuck compiler and virtual machine
+//!
+//! The stable surface for embedders is the [`prelude`] together with the modules, that are part of the documentation.
+//! Changes to them follow semver: anything removed or changed incompatibly needs a new major version.
+//! The hidden modules only exist for the command line tool and may change in any release.
+
+use clap::{Parser, Subcommand, ValueHint};
+use core::fmt::Display;
 use std::{io, fs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use compiler::{CompileOptions, Extension, Pass, DEFAULT_TAB_WIDTH};
+use diagnostic::ErrorStyle;
+use encoding::{DecodeError, Encoding};
+use engine::EngineKind;
+use failure::Failure;
+use input::{EofPolicy, InputPrefix};
+use vm::{OverflowPolicy, PointerPolicy, RunLimits};
 
+#[doc(hidden)]
+pub mod cast;
 pub mod compiler;
+#[doc(hidden)]
+pub mod console;
+#[doc(hidden)]
+pub mod decompress;
+pub mod diagnostic;
+#[doc(hidden)]
+pub mod diff;
+pub mod encoding;
+pub mod engine;
+pub mod error;
+#[doc(hidden)]
+pub mod explain;
+#[doc(hidden)]
+pub mod exporter;
+pub mod failure;
+#[cfg(feature = "http")]
+#[doc(hidden)]
+pub mod fetch;
+#[doc(hidden)]
+pub mod fingerprint;
+#[doc(hidden)]
+pub mod fuzz;
+#[doc(hidden)]
+pub mod generate;
+#[doc(hidden)]
+pub mod golf;
+pub mod input;
+#[cfg(feature = "jupyter")]
+#[doc(hidden)]
+pub mod kernel;
+#[doc(hidden)]
+pub mod loops;
+pub mod metrics;
+#[cfg(feature = "png")]
+#[doc(hidden)]
+pub mod portrait;
+pub mod prelude;
+#[doc(hidden)]
+pub mod profile;
+#[doc(hidden)]
+pub mod project;
+#[doc(hidden)]
+pub mod report;
+#[doc(hidden)]
+pub mod repeats;
+#[doc(hidden)]
+pub mod rpc;
+pub mod script;
+pub mod state;
+pub mod stats;
+#[cfg(feature = "tokio")]
+pub mod stream;
+pub mod syntax;
+pub mod tape;
+#[doc(hidden)]
+pub mod targets;
 pub mod vm;
 
+// options that aren't given on the command line may be set by a `bf.toml` (see `project`)
 #[derive(Parser)]
-#[command(version)]
+#[command(version, about, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Config {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// File OR programcode [default: File]
-    program: String,
+    ///
+    /// A directory or glob pattern runs every `*.b`/`*.bf` file found and reports a summary.
+    /// With the `http` feature, the program can also be downloaded from an http(s) url.
+    #[arg(required = true, value_hint = ValueHint::AnyPath)]
+    program: Option<String>,
 
     /// Amount of cells available
     #[arg(default_value_t = 30000, short = 'c', long = "cells")]
@@ -18,22 +101,412 @@ pub struct Config {
     #[arg(short = 'i', long = "input", action)]
     inp_type: bool,
 
+    /// Encoding of the programcode given with --input
+    #[arg(long = "encoding", value_enum, requires = "inp_type")]
+    encoding: Option<Encoding>,
+
     /// If program should be optimized
     #[arg(short = 'o', long = "optimize", action)]
     pub optimize: bool,
+
+    /// Optimizer passes to run with --optimize
+    #[arg(long = "passes", value_enum, value_delimiter = ',', default_values_t = Pass::ALL)]
+    pub passes: Vec<Pass>,
+
+    /// Width of a tab, used for the columns in error messages
+    #[arg(long = "tab-width", default_value_t = DEFAULT_TAB_WIDTH)]
+    pub tab_width: usize,
+
+    /// How parse errors are printed, `short` prints one `file:line:col: message` line per error
+    #[arg(long = "error-style", value_enum, default_value_t = ErrorStyle::Full)]
+    pub error_style: ErrorStyle,
+
+    /// Treat compiler warnings as errors
+    #[arg(long = "deny-warnings", action)]
+    pub deny_warnings: bool,
+
+    /// Print the instruction counts after parsing and around every optimizer pass to stderr
+    #[arg(long = "compile-stats", action)]
+    pub compile_stats: bool,
+
+    /// Maximum loop nesting depth, deeper programs are rejected while parsing
+    #[arg(long = "max-depth")]
+    pub max_depth: Option<usize>,
+
+    /// Language extensions to enable
+    #[arg(long = "ext", value_enum, value_delimiter = ',')]
+    pub extensions: Vec<Extension>,
+
+    /// Amount of tapes available with the multi-tape extension
+    #[arg(default_value_t = 2, long = "tapes")]
+    pub tapes: usize,
+
+    /// Seed for the rand extension, a time based seed is used if not set
+    #[arg(long = "seed")]
+    pub seed: Option<u64>,
+
+    /// Input consumed by `,` before stdin is read, supports escapes like `\n` and `\xHH`
+    #[arg(long = "args", value_parser = input::parse_escaped)]
+    pub args: Option<InputPrefix>,
+
+    /// Script with `send "..."`, `expect "..."` and `wait-steps N` lines, that provides the input instead of stdin
+    #[arg(long = "input-script", value_hint = ValueHint::FilePath)]
+    pub input_script: Option<PathBuf>,
+
+    /// Prompt printed to stderr when `,` waits for input from a terminal, e.g. "?> "
+    #[arg(long = "prompt")]
+    pub prompt: Option<String>,
+
+    /// Value stored by `,` once the input ended
+    #[arg(long = "eof", value_enum, default_value_t = EofPolicy::Zero)]
+    pub eof: EofPolicy,
+
+    /// Backend, that executes the program
+    #[arg(long = "engine", value_enum, default_value_t = EngineKind::Interp)]
+    pub engine: EngineKind,
+
+    /// Run slowly and describe every instruction in plain English on stderr
+    #[arg(long = "explain", action)]
+    pub explain: bool,
+
+    /// Milliseconds to wait after every explained instruction
+    #[arg(long = "explain-delay", default_value_t = 500, requires = "explain")]
+    pub explain_delay: u64,
+
+    /// With --explain, wait for enter at the start of every loop
+    #[arg(long = "explain-pause", action, requires = "explain")]
+    pub explain_pause: bool,
+
+    /// Show the source colored by how often every command ran on stderr after the program ran
+    #[arg(long = "heatmap", action, conflicts_with_all = ["optimize", "explain"])]
+    pub heatmap: bool,
+
+    /// Record the output of the run with its timing as an asciinema v2 cast
+    #[arg(long = "record-cast", value_hint = ValueHint::FilePath, conflicts_with_all = ["explain", "heatmap"])]
+    pub record_cast: Option<PathBuf>,
+
+    /// Render the values of the cells over time as a png, one row per sample and one column per cell
+    ///
+    /// Requires the `png` feature.
+    #[arg(long = "render-tape-history", value_hint = ValueHint::FilePath, conflicts_with_all = ["explain", "heatmap", "record_cast"])]
+    pub render_tape_history: Option<PathBuf>,
+
+    /// Stop the program after this many steps
+    #[arg(long = "max-steps")]
+    pub max_steps: Option<u64>,
+
+    /// Stop the program after this many milliseconds
+    #[arg(long = "timeout")]
+    pub timeout: Option<u64>,
+
+    /// Stop the program once it printed this many bytes
+    #[arg(long = "max-output")]
+    pub max_output: Option<u64>,
+
+    /// Refuse to run, if the cells of all tapes together would need more bytes
+    #[arg(long = "max-memory")]
+    pub max_memory: Option<usize>,
+
+    /// What `<` and `>` do, when the pointer would leave the tape
+    #[arg(long = "pointer-bounds", value_enum, default_value_t = PointerPolicy::Strict)]
+    pub pointer_bounds: PointerPolicy,
+
+    /// What `+` and `-` do, when a cell goes past 255 or below 0 [default: wrap]
+    #[arg(long = "cell-overflow", value_enum)]
+    pub cell_overflow: Option<OverflowPolicy>,
+
+    /// Conservative defaults for untrusted programs
+    ///
+    /// Sets a step limit, a timeout, an output and a memory cap and makes cell overflows an error,
+    /// unless the corresponding option is given. Only inline programs (--input) are accepted and no input script is read.
+    #[arg(long = "sandbox", action)]
+    pub sandbox: bool,
+
+    /// Print the non-zero cells of the tape to stderr after the program ran
+    #[arg(short = 'd', long = "dump", action)]
+    pub dump: bool,
+}
+
+/// Subcommands, that do something else than running a program
+#[derive(Subcommand)]
+pub enum Command {
+    /// Print a completion script for the given shell to stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Score a program for code golf: source bytes, commands and what could still be removed
+    Golf {
+        #[arg(value_hint = ValueHint::FilePath)]
+        program: PathBuf,
+        /// Only count the eight standard commands, extension commands are comments
+        #[arg(long = "strict", action)]
+        strict: bool,
+    },
+    /// Print a digest of the commands of a program, that ignores comments and formatting
+    Hash {
+        #[arg(value_hint = ValueHint::FilePath)]
+        program: PathBuf,
+        /// Language extensions, whose commands are part of the program
+        #[arg(long = "ext", value_enum, value_delimiter = ',')]
+        extensions: Vec<Extension>,
+    },
+    /// Serve JSON-RPC requests on stdin, one per line, for editors and other frontends
+    ///
+    /// Methods: compile, run, step, inspectTape and setBreakpoint.
+    Rpc {
+        /// Serve prometheus metrics at http://ADDR/metrics, e.g. 127.0.0.1:9100
+        #[arg(long = "metrics", value_name = "ADDR")]
+        metrics: Option<std::net::SocketAddr>,
+    },
+    /// Run as a jupyter kernel, every cell runs on the same machine
+    ///
+    /// A cell starting with %%reset runs on a fresh machine, %%tape prints the non-zero cells after the cell ran.
+    Kernel {
+        /// Connection file written by jupyter, pass it as `{connection_file}` in the kernel spec
+        #[arg(long = "connection-file", value_hint = ValueHint::FilePath)]
+        connection_file: PathBuf,
+    },
+    /// Print a generated program to stdout
+    Generate {
+        #[command(subcommand)]
+        generator: Generator,
+    },
+    /// Compare the commands of two programs, ignoring comments and formatting
+    Diff {
+        #[arg(value_hint = ValueHint::FilePath)]
+        old: PathBuf,
+        #[arg(value_hint = ValueHint::FilePath)]
+        new: PathBuf,
+        /// Language extensions, whose commands are part of the programs
+        #[arg(long = "ext", value_enum, value_delimiter = ',')]
+        extensions: Vec<Extension>,
+    },
+    /// Export the nesting tree of the loops, an html report of a profiled run or repeated command sequences
+    #[command(group(clap::ArgGroup::new("output").args(["loops", "report", "repeats"]).required(true).multiple(true)))]
+    Analyze {
+        #[arg(value_hint = ValueHint::FilePath)]
+        program: PathBuf,
+        /// File for the loop tree, `.dot` for graphviz or `.html`
+        #[arg(long = "loops", value_hint = ValueHint::FilePath)]
+        loops: Option<PathBuf>,
+        /// Run the program and write an html report, that colors the source by how often every command ran
+        #[arg(long = "report", value_hint = ValueHint::FilePath)]
+        report: Option<PathBuf>,
+        /// Print long command sequences, that occur several times, and how much extracting them would save
+        #[arg(long = "repeats", action)]
+        repeats: bool,
+        /// Run the program first and annotate every loop with its iteration count
+        #[arg(long = "profile", action)]
+        profile: bool,
+        /// Language extensions, whose commands are part of the program
+        #[arg(long = "ext", value_enum, value_delimiter = ',')]
+        extensions: Vec<Extension>,
+    },
+    /// Run random programs on several engines and report the first case, where they disagree
+    Fuzz {
+        /// Engines to compare, at least two
+        #[arg(long = "engines", value_enum, value_delimiter = ',', default_value = "interp,optimized")]
+        engines: Vec<fuzz::Engine>,
+        /// Amount of random programs to run
+        #[arg(long = "iterations", default_value_t = 1000)]
+        iterations: u64,
+        /// Steps after which a program is skipped
+        #[arg(long = "max-steps", default_value_t = 100_000)]
+        max_steps: u64,
+        /// Seed for the generated programs, a time based seed is used if not set
+        #[arg(long = "seed")]
+        seed: Option<u64>,
+    },
+}
+
+/// Programs, that `generate` can produce
+#[derive(Subcommand)]
+pub enum Generator {
+    /// A program, that prints the exact bytes of a (possibly binary) file
+    Data {
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+    },
+}
+
+/// Errors that can occur while obtaining the program text
+#[derive(Debug)]
+pub enum ProgramError {
+    Io(io::Error),
+    Decode(DecodeError),
+    #[cfg(feature = "http")]
+    Http(String),
+}
+
+impl Display for ProgramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgramError::Io(err) => write!(f, "Error while reading the Input file:\n{}", err),
+            ProgramError::Decode(err) => write!(f, "{}", err),
+            #[cfg(feature = "http")]
+            ProgramError::Http(err) => write!(f, "Error while downloading the program:\n{}", err),
+        }
+    }
+}
+
+impl ProgramError {
+    pub fn failure(&self) -> Failure {
+        match self {
+            ProgramError::Io(_) => Failure::Io,
+            ProgramError::Decode(_) => Failure::Parse,
+            #[cfg(feature = "http")]
+            ProgramError::Http(_) => Failure::Io,
+        }
+    }
+}
+
+impl From<io::Error> for ProgramError {
+    fn from(err: io::Error) -> Self {
+        ProgramError::Io(err)
+    }
+}
+
+impl From<DecodeError> for ProgramError {
+    fn from(err: DecodeError) -> Self {
+        ProgramError::Decode(err)
+    }
 }
 
+/// read a program file from disk
+/// compressed files (e.g. `.b.gz`, `.b.zst`) are detected by their magic bytes
+/// bytes that aren't valid UTF-8 can only be part of comments, they are replaced by `U+FFFD`,
+/// so every invalid sequence counts as one column in the reported positions
+pub fn read_program_file(path: &Path) -> Result<String, ProgramError> {
+    let contents = decompress::decompress(fs::read(path)?)?;
+    let program = match String::from_utf8(contents) {
+        Ok(program) => program,
+        Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+    };
+    Ok(program)
+}
+
+/// true if the program argument should be downloaded instead of read from disk
+fn is_url(program: &str) -> bool {
+    program.starts_with("http://") || program.starts_with("https://")
+}
+
+/// limits of `--sandbox`, for the options that aren't given
+const SANDBOX_MAX_STEPS: u64 = 10_000_000;
+const SANDBOX_TIMEOUT_MS: u64 = 5_000;
+const SANDBOX_MAX_OUTPUT: u64 = 1 << 20;
+const SANDBOX_MAX_MEMORY: usize = 1 << 20;
+
 impl Config {
+    /// amount of tapes the machine gets, more than one only with the multi-tape extension
+    pub fn tape_count(&self) -> usize {
+        if self.extensions.contains(&Extension::MultiTape) { self.tapes.max(1) } else { 1 }
+    }
+
+    /// limits of a run, with `--sandbox` the ones not given get conservative defaults
+    pub fn run_limits(&self) -> RunLimits {
+        let sandbox = |value| Some(value).filter(|_| self.sandbox);
+        RunLimits {
+            max_steps: self.max_steps.or(sandbox(SANDBOX_MAX_STEPS)),
+            max_output_bytes: self.max_output.or(sandbox(SANDBOX_MAX_OUTPUT)),
+            timeout: self.timeout.or(sandbox(SANDBOX_TIMEOUT_MS)).map(Duration::from_millis),
+        }
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        let default = if self.sandbox { OverflowPolicy::Error } else { OverflowPolicy::Wrap };
+        self.cell_overflow.unwrap_or(default)
+    }
+
+    /// checks, that have to pass before anything is read or run
+    /// with `--sandbox` the program has to be given inline and the tapes must fit the memory cap
+    pub fn check_limits(&self) -> Result<(), String> {
+        if self.sandbox && !self.inp_type {
+            return Err(String::from("--sandbox only runs programs given inline with --input"));
+        }
+        if self.sandbox && self.input_script.is_some() {
+            return Err(String::from("--sandbox doesn't read input scripts"));
+        }
+        let max_memory = self.max_memory.or(Some(SANDBOX_MAX_MEMORY).filter(|_| self.sandbox));
+        let memory = self.cell_sz.saturating_mul(self.tape_count());
+        if let Some(max) = max_memory.filter(|max| memory > *max) {
+            return Err(format!("The tapes need {memory} bytes, but at most {max} are allowed"));
+        }
+        Ok(())
+    }
+
+    /// collect the options relevant for compiling the program
+    pub fn compile_options(&self) -> CompileOptions {
+        CompileOptions {
+            optimize: self.optimize,
+            extensions: self.extensions.clone(),
+            tab_width: self.tab_width,
+            max_depth: self.max_depth,
+            overflow: self.overflow_policy(),
+            passes: self.passes.clone(),
+        }
+    }
+
+    /// the directory where the search for a `bf.toml` starts
+    /// that is the directory of the program file (or pattern), or the current directory for inline programs
+    pub fn project_dir(&self) -> PathBuf {
+        let arg = self.program.as_deref().unwrap_or_default();
+        let program = Path::new(arg);
+        let dir = if self.inp_type || is_url(arg) {
+            Path::new(".")
+        } else if program.is_dir() {
+            program
+        } else {
+            // an empty parent means the file is in the current directory
+            program.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."))
+        };
+        dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf())
+    }
+
+    /// name of the program in error messages, the file or url, or `<input>` for inline programs
+    /// has to be called before `get_program`, which replaces the argument with the programcode
+    pub fn source_name(&self) -> String {
+        match &self.program {
+            Some(program) if !self.inp_type => program.clone(),
+            _ => String::from("<input>"),
+        }
+    }
+
+    /// if the program argument is a directory or glob pattern, return all program files it expands to
+    /// `None` means a single program should be run
+    pub fn get_targets(&self) -> Result<Option<Vec<PathBuf>>, ProgramError> {
+        let arg = self.program.as_deref().unwrap_or_default();
+        if self.inp_type || is_url(arg) || !targets::is_multi_target(arg) {
+            return Ok(None);
+        }
+        Ok(Some(targets::expand(arg)?))
+    }
+
     /// return the correct bf program as a string slice
     /// if inp_type isnt set, the file will be read and placed into the program field
-    pub fn get_program(&mut self) -> Result<&str, io::Error> {
+    /// an encoded inline program is decoded in place
+    pub fn get_program(&mut self) -> Result<&str, ProgramError> {
+        let program = self.program.get_or_insert_with(String::new);
         if self.inp_type {
-            Ok(&self.program)
+            if let Some(encoding) = self.encoding.take() {
+                *program = encoding.decode(program)?;
+            }
+            Ok(program)
         } else {
-            let contents = fs::read_to_string(self.program.clone())?;
-            self.program = contents;
-            self.inp_type = false;
-            Ok(&self.program)
+            if is_url(program) {
+                #[cfg(feature = "http")]
+                {
+                    *program = fetch::fetch_program(program).map_err(ProgramError::Http)?;
+                    self.inp_type = true;
+                    return Ok(program);
+                }
+                #[cfg(not(feature = "http"))]
+                return Err(ProgramError::Io(io::Error::new(io::ErrorKind::Unsupported, "downloading programs requires the `http` feature")));
+            }
+
+            *program = read_program_file(Path::new(program))?;
+            self.inp_type = true;
+            Ok(program)
         }
     }
 }
@@ -0,0 +1,97 @@
+use crate::compiler::{Instruction, Position, Program};
+use crate::profile::Profile;
+
+/// A loop of the program with the loops nested inside of it
+#[derive(Debug, PartialEq, Clone)]
+pub struct LoopNode {
+    pub open: Position,
+    pub close: Position,
+    /// instructions inside the loop, including the nested loops
+    pub body: usize,
+    /// how often the body ran, if the program was profiled
+    pub iterations: Option<u64>,
+    pub children: Vec<LoopNode>,
+}
+
+impl LoopNode {
+    fn label(&self) -> String {
+        let mut label = format!("{}:{}..{}:{}, body: {}", self.open.line, self.open.col, self.close.line, self.close.col, self.body);
+        if let Some(iterations) = self.iterations {
+            label.push_str(&format!(", iterations: {iterations}"));
+        }
+        label
+    }
+}
+
+/// The outermost loops of an unoptimized program, the positions come from its spans
+pub fn loop_tree(program: &Program, profile: Option<&Profile>) -> Vec<LoopNode> {
+    collect(program, profile, 0, program.len())
+}
+
+fn collect(program: &Program, profile: Option<&Profile>, start: usize, end: usize) -> Vec<LoopNode> {
+    let mut nodes = Vec::new();
+    let mut index = start;
+    while index < end {
+        let Instruction::JmpZ(close) = program[index] else {
+            index += 1;
+            continue;
+        };
+        nodes.push(LoopNode {
+            open: program.span(index).expect("unoptimized programs have spans"),
+            close: program.span(close).expect("unoptimized programs have spans"),
+            body: close - index - 1,
+            // the `Jmp` at the end runs once per iteration
+            iterations: profile.map(|profile| profile.count(close)),
+            children: collect(program, profile, index + 1, close),
+        });
+        index = close + 1;
+    }
+    nodes
+}
+
+/// Graphviz graph of the loop tree, with the program as the root
+pub fn render_dot(nodes: &[LoopNode]) -> String {
+    fn node(dot: &mut String, loop_node: &LoopNode, parent: &str, next_id: &mut usize) {
+        let id = format!("loop{next_id}");
+        *next_id += 1;
+        dot.push_str(&format!("    {id} [label=\"{}\"];\n    {parent} -> {id};\n", loop_node.label()));
+        for child in &loop_node.children {
+            node(dot, child, &id, next_id);
+        }
+    }
+
+    let mut dot = String::from("digraph loops {\n    node [shape=box];\n    program;\n");
+    let mut next_id = 0;
+    for loop_node in nodes {
+        node(&mut dot, loop_node, "program", &mut next_id);
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Self-contained html page with the loop tree as nested lists
+pub fn render_html(nodes: &[LoopNode], title: &str) -> String {
+    fn list(html: &mut String, nodes: &[LoopNode]) {
+        if nodes.is_empty() { return; }
+        html.push_str("<ul>\n");
+        for loop_node in nodes {
+            html.push_str(&format!("<li>{}", loop_node.label()));
+            list(html, &loop_node.children);
+            html.push_str("</li>\n");
+        }
+        html.push_str("</ul>\n");
+    }
+
+    let title = escape_html(title);
+    let mut html = format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Loops of {title}</title>\n</head>\n<body>\n<h1>Loops of {title}</h1>\n");
+    if nodes.is_empty() {
+        html.push_str("<p>The program has no loops.</p>\n");
+    }
+    list(&mut html, nodes);
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -1,30 +1,280 @@
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
+use std::io::Write;
+use std::path::PathBuf;
 use std::process;
 use bf_interpreter::*;
+use bf_interpreter::engine::Engine;
+use bf_interpreter::failure::Failure;
 
-fn main() {
-    let mut cnfg = Config::parse();
-    let optimize = cnfg.optimize;
+/// print the message and exit with the code of the failure class
+fn fail(failure: Failure, msg: impl std::fmt::Display) -> ! {
+    let _ = std::io::stdout().flush();
+    eprintln!("{msg}");
+    process::exit(failure.exit_code());
+}
 
-    let program_str = match cnfg.get_program() {
-        Ok(str) => str,
-        Err(err) => {
-            eprintln!("Error while reading the Input file:\n{err}");
-            process::exit(1);
+/// write an exported file, exiting if that fails
+fn write_file(path: &std::path::Path, contents: &str) {
+    if let Err(err) = std::fs::write(path, contents) {
+        fail(Failure::Io, format!("Error while writing {}:\n{err}", path.display()));
+    }
+}
+
+/// compile and run a single program, returning the failure class and error message on failure
+fn run(cnfg: &Config, name: &str, program_str: &str) -> Result<(), (Failure, String)> {
+    // the tiered engine optimizes the loops itself, once it knows which of them are hot
+    let optimize = cnfg.optimize && cnfg.engine != engine::EngineKind::Tiered;
+    let options = compiler::CompileOptions { optimize, ..cnfg.compile_options() };
+    let program = compiler::Program::compile(program_str, &options)
+        .map_err(|err| (Failure::Parse, err.render(cnfg.error_style, name, program_str)))?;
+
+    if cnfg.compile_stats {
+        eprintln!("{}", program.stats());
+    }
+
+    let warnings: String = program.warnings().iter()
+        .map(|warning| warning.render_style(cnfg.error_style, name, program_str, cnfg.tab_width))
+        .collect();
+    if cnfg.deny_warnings && !warnings.is_empty() {
+        return Err((Failure::Parse, format!("{warnings}warnings are denied by --deny-warnings")));
+    }
+    eprint!("{warnings}");
+
+    let mut machine = vm::Machine::new(cnfg);
+    if let Some(path) = &cnfg.input_script {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| (Failure::Io, format!("Error while reading the input script:\n{err}")))?;
+        let script = script::InputScript::parse(&contents).map_err(|err| (Failure::Usage, err))?;
+        machine.set_input_script(script);
+    }
+    #[cfg(all(unix, feature = "signals"))]
+    {
+        // SIGUSR1 prints the current state of the machine without interrupting it
+        let flag = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        if signal_hook::flag::register(signal_hook::consts::SIGUSR1, flag.clone()).is_ok() {
+            machine.set_dump_flag(flag);
+        }
+    }
+    let mut profile = None;
+    let mut engine: Box<dyn Engine> = match cnfg.engine {
+        engine::EngineKind::Interp => Box::new(engine::Interpreter::new(&mut machine)),
+        engine::EngineKind::Tiered => Box::new(engine::Tiered::new(&mut machine).passes(cnfg.passes.clone())),
+    };
+    let result = if cnfg.explain {
+        let delay = std::time::Duration::from_millis(cnfg.explain_delay);
+        explain::Explainer::new(delay, cnfg.explain_pause).run(engine.as_mut(), &program)
+    } else if let Some(path) = &cnfg.record_cast {
+        let (cast, result) = cast::Cast::record(engine.as_mut(), &program, name);
+        std::fs::write(path, cast.to_string())
+            .map_err(|err| (Failure::Io, format!("Error while writing {}:\n{err}", path.display())))?;
+        result
+    } else if let Some(path) = &cnfg.render_tape_history {
+        #[cfg(feature = "png")]
+        {
+            let (history, result) = portrait::TapeHistory::record(engine.as_mut(), &program);
+            history.write_png(path)
+                .map_err(|err| (Failure::Io, format!("Error while writing {}:\n{err}", path.display())))?;
+            result
         }
+        #[cfg(not(feature = "png"))]
+        return Err((Failure::Usage, format!("Rendering the tape history to {} requires the `png` feature", path.display())));
+    } else if cnfg.heatmap {
+        let (recorded, result) = profile::Profile::record(engine.as_mut(), &program);
+        profile = Some(recorded);
+        result
+    } else {
+        engine.load(&program);
+        engine.run(cnfg.run_limits())
     };
+    drop(engine);
+    if cnfg.dump {
+        eprintln!("\n{}", machine.dump_nonzero());
+    }
+    if let Some(profile) = profile {
+        eprintln!("\n{}", report::render_heatmap(program_str, &program, &profile));
+    }
+    result.map_err(|err| (err.failure(), err.to_string()))
+}
+
+/// run every program file and print a summary
+/// if any program failed, exits with the code of the first failure
+fn run_all(cnfg: &Config, files: Vec<PathBuf>) {
+    if files.is_empty() {
+        fail(Failure::Usage, "No program files found");
+    }
 
-    let program = match compiler::Program::from_str(program_str, optimize) {
-        Ok(program) => program,
-        Err(err) => {
-            eprintln!("{}", err.get_error_msg(program_str));
-            process::exit(1);
+    let mut failed = 0;
+    let mut first_failure = None;
+    for file in &files {
+        let result = read_program_file(file)
+            .map_err(|err| (err.failure(), err.to_string()))
+            .and_then(|program_str| run(cnfg, &file.display().to_string(), &program_str));
+        // keep the program output and the report in order
+        let _ = std::io::stdout().flush();
+        match result {
+            Ok(()) => eprintln!("\n[ok] {}", file.display()),
+            Err((failure, err)) => {
+                failed += 1;
+                first_failure.get_or_insert(failure);
+                eprintln!("\n[failed] {}\n{err}", file.display());
+            }
         }
+    }
+
+    eprintln!("\n{} programs run: {} ok, {} failed", files.len(), files.len() - failed, failed);
+    if let Some(failure) = first_failure {
+        process::exit(failure.exit_code());
+    }
+}
+
+fn main() {
+    let matches = Config::command().try_get_matches().unwrap_or_else(|err| {
+        // --help and --version are reported as errors by clap, but aren't failures
+        let _ = err.print();
+        process::exit(if err.use_stderr() { Failure::Usage.exit_code() } else { 0 });
+    });
+    let mut cnfg = Config::from_arg_matches(&matches).unwrap_or_else(|err| fail(Failure::Usage, err));
+    if !console::init() && cnfg.heatmap {
+        fail(Failure::Usage, "--heatmap needs a console, that supports ANSI colors");
+    }
+
+    match &cnfg.command {
+        Some(Command::Completions { shell }) => {
+            let mut cmd = Config::command();
+            let name = cmd.get_name().to_string();
+            clap_complete::generate(*shell, &mut cmd, name, &mut std::io::stdout());
+            return;
+        },
+        Some(Command::Golf { program, strict }) => {
+            let source = read_program_file(program).unwrap_or_else(|err| fail(err.failure(), err));
+            println!("{}", golf::GolfReport::new(&source, *strict));
+            return;
+        },
+        Some(Command::Hash { program, extensions }) => {
+            let source = read_program_file(program).unwrap_or_else(|err| fail(err.failure(), err));
+            let options = compiler::CompileOptions { extensions: extensions.clone(), ..Default::default() };
+            match fingerprint::fingerprint(&source, &options) {
+                Ok(hash) => println!("{hash:016x}  {}", program.display()),
+                Err(err) => fail(Failure::Parse, err.render(cnfg.error_style, &program.display().to_string(), &source)),
+            }
+            return;
+        },
+        Some(Command::Rpc { metrics: addr }) => {
+            let metrics = addr.map(|addr| {
+                let metrics = exporter::ServiceMetrics::default();
+                exporter::serve(addr, metrics.clone())
+                    .unwrap_or_else(|err| fail(Failure::Io, format!("Error while listening on {addr}:\n{err}")));
+                metrics
+            });
+            if let Err(err) = rpc::serve_stdio(&cnfg, metrics) {
+                fail(Failure::Io, format!("Error while serving requests:\n{err}"));
+            }
+            return;
+        },
+        Some(Command::Kernel { connection_file }) => {
+            #[cfg(feature = "jupyter")]
+            {
+                if let Err(err) = kernel::run_kernel(&cnfg, connection_file) {
+                    fail(Failure::Io, err);
+                }
+                return;
+            }
+            #[cfg(not(feature = "jupyter"))]
+            fail(Failure::Usage, format!("Running a kernel for {} requires the `jupyter` feature", connection_file.display()));
+        },
+        Some(Command::Generate { generator: Generator::Data { file } }) => {
+            let bytes = std::fs::read(file)
+                .unwrap_or_else(|err| fail(Failure::Io, format!("Error while reading {}:\n{err}", file.display())));
+            print!("{}", generate::data_program(&bytes));
+            return;
+        },
+        Some(Command::Diff { old, new, extensions }) => {
+            let old_source = read_program_file(old).unwrap_or_else(|err| fail(err.failure(), err));
+            let new_source = read_program_file(new).unwrap_or_else(|err| fail(err.failure(), err));
+            let options = compiler::CompileOptions { extensions: extensions.clone(), ..Default::default() };
+            let diff = diff::ProgramDiff::new(&old_source, &new_source, &options);
+            println!("{}", diff.render(&old.display().to_string(), &new.display().to_string()));
+            return;
+        },
+        Some(Command::Analyze { program: path, loops: loops_out, report, repeats, profile, extensions }) => {
+            let html = match loops_out.as_ref().map(|out| out.extension().and_then(|ext| ext.to_str())) {
+                None | Some(Some("dot")) => false,
+                Some(Some("html")) => true,
+                Some(_) => fail(Failure::Usage, "The loop tree can only be written to a .dot or .html file"),
+            };
+            let name = path.display().to_string();
+            let source = read_program_file(path).unwrap_or_else(|err| fail(err.failure(), err));
+            let options = compiler::CompileOptions { extensions: extensions.clone(), ..Default::default() };
+            let program = compiler::Program::compile(&source, &options)
+                .unwrap_or_else(|err| fail(Failure::Parse, err.render(cnfg.error_style, &name, &source)));
+            let profile = (*profile || report.is_some()).then(|| {
+                let (profile, result) = profile::Profile::record(&mut engine::Interpreter::new(&mut vm::Machine::new(&cnfg)), &program);
+                if let Err(err) = result {
+                    fail(err.failure(), err);
+                }
+                profile
+            });
+            if let Some(out) = loops_out {
+                let tree = loops::loop_tree(&program, profile.as_ref());
+                let rendered = if html { loops::render_html(&tree, &name) } else { loops::render_dot(&tree) };
+                write_file(out, &rendered);
+            }
+            if let (Some(out), Some(profile)) = (report, &profile) {
+                write_file(out, &report::render_report(&name, &source, &program, profile));
+            }
+            if *repeats {
+                let found = repeats::find_repeats(&source, &options);
+                if found.is_empty() {
+                    println!("no repeated sequences worth extracting");
+                }
+                for repeat in found {
+                    println!("{repeat}");
+                }
+            }
+            return;
+        },
+        Some(Command::Fuzz { engines, iterations, max_steps, seed }) => {
+            if engines.len() < 2 {
+                fail(Failure::Usage, "fuzzing needs at least two engines to compare");
+            }
+            let seed = seed.unwrap_or_else(|| {
+                std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|time| time.as_nanos() as u64).unwrap_or(0)
+            });
+            let mut fuzzer = fuzz::Fuzzer::new(&cnfg, engines.clone(), *max_steps, seed);
+            if let Some(divergence) = fuzzer.run(*iterations) {
+                fail(Failure::Runtime, format!("{divergence}\n(seed {seed})"));
+            }
+            println!("{iterations} programs run ({} skipped at the step limit), no divergence (seed {seed})", fuzzer.skipped);
+            return;
+        },
+        None => {},
+    }
+
+    if let Some(path) = project::ProjectConfig::discover(&cnfg.project_dir()) {
+        let applied = project::ProjectConfig::load(&path)
+            .and_then(|project| project.apply(&mut cnfg, &matches, &path));
+        if let Err(err) = applied {
+            fail(Failure::Usage, err);
+        }
+    }
+
+    if let Err(err) = cnfg.check_limits() {
+        fail(Failure::Usage, err);
+    }
+
+    match cnfg.get_targets() {
+        Ok(Some(files)) => return run_all(&cnfg, files),
+        Ok(None) => {},
+        Err(err) => fail(err.failure(), err),
+    }
+
+    let name = cnfg.source_name();
+    let program_str = match cnfg.get_program() {
+        Ok(str) => str.to_string(),
+        Err(err) => fail(err.failure(), err),
     };
 
-    let mut machine = vm::Machine::new(&cnfg);
-    if let Err(err) = machine.run(&program) {
-        eprintln!("{}", err);
-        process::exit(1);
+    if let Err((failure, err)) = run(&cnfg, &name, &program_str) {
+        fail(failure, err);
     }
 }
@@ -0,0 +1,43 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of a machine at one point of the execution
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Metrics {
+    pub steps: u64,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// how often a loop body was entered
+    pub loop_iterations: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    steps: AtomicU64,
+    input_bytes: AtomicU64,
+    output_bytes: AtomicU64,
+    loop_iterations: AtomicU64,
+}
+
+/// Counters of a machine, that can be read from another thread while it runs
+/// the machine publishes its counters every few steps, so they may lag slightly behind
+#[derive(Debug, Clone, Default)]
+pub struct LiveMetrics(Arc<Counters>);
+
+impl LiveMetrics {
+    pub fn snapshot(&self) -> Metrics {
+        Metrics {
+            steps: self.0.steps.load(Ordering::Relaxed),
+            input_bytes: self.0.input_bytes.load(Ordering::Relaxed),
+            output_bytes: self.0.output_bytes.load(Ordering::Relaxed),
+            loop_iterations: self.0.loop_iterations.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn publish(&self, metrics: &Metrics) {
+        self.0.steps.store(metrics.steps, Ordering::Relaxed);
+        self.0.input_bytes.store(metrics.input_bytes, Ordering::Relaxed);
+        self.0.output_bytes.store(metrics.output_bytes, Ordering::Relaxed);
+        self.0.loop_iterations.store(metrics.loop_iterations, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,79 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use crate::compiler::Program;
+use crate::engine::Engine;
+use crate::tape::Tape;
+use crate::vm::{Machine, RuntimeError};
+
+/// rows kept at most, once reached every second row is dropped and the sample interval doubles
+const MAX_ROWS: usize = 1024;
+/// narrow tapes are scaled up to be at least this many pixels wide
+const MIN_WIDTH: usize = 256;
+
+/// Values of the cells over time, one row per sample and one column per cell
+/// only the cells up to the rightmost one the pointer reached are kept
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TapeHistory {
+    pub rows: Vec<Vec<u8>>,
+    /// steps between two rows
+    pub interval: u64,
+}
+
+impl TapeHistory {
+    /// run the program and sample the active tape
+    /// the history is returned even if the run failed, it then ends at the error
+    pub fn record<T: Tape>(engine: &mut dyn Engine<T>, program: &Program) -> (TapeHistory, Result<(), RuntimeError>) {
+        let mut history = TapeHistory { rows: Vec::new(), interval: 1 };
+        let mut width = 1;
+        let mut steps = 0u64;
+        engine.load(program);
+        let result = loop {
+            width = width.max(engine.machine().ptr() + 1);
+            if steps.is_multiple_of(history.interval) {
+                history.sample(engine.machine(), width);
+            }
+            match engine.step() {
+                Ok(true) => {},
+                Ok(false) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+            steps += 1;
+        };
+        history.sample(engine.machine(), width);
+        (history, result)
+    }
+
+    fn sample<T: Tape>(&mut self, machine: &Machine<T>, width: usize) {
+        let mut cells = machine.snapshot().cells;
+        cells.truncate(width);
+        self.rows.push(cells);
+        if self.rows.len() >= MAX_ROWS {
+            self.rows = self.rows.iter().step_by(2).cloned().collect();
+            self.interval *= 2;
+        }
+    }
+
+    /// Write the history as a grayscale png, the brightness of a pixel is the value of the cell
+    pub fn write_png(&self, path: &Path) -> io::Result<()> {
+        let cells = self.rows.iter().map(Vec::len).max().unwrap_or(1);
+        let scale = MIN_WIDTH.div_ceil(cells).max(1);
+        let width = cells * scale;
+
+        let mut data = Vec::with_capacity(width * self.rows.len());
+        for row in &self.rows {
+            for index in 0..cells {
+                let value = row.get(index).copied().unwrap_or(0);
+                data.extend(std::iter::repeat_n(value, scale));
+            }
+        }
+
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width as u32, self.rows.len() as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,25 @@
+//! The types most embedders need, `use bf_interpreter::prelude::*;`
+//!
+//! ```no_run
+//! use bf_interpreter::prelude::*;
+//!
+//! fn main() -> Result<(), BfError> {
+//!     let program = Program::compile("++++++++[>++++++++<-]>+.", &CompileOptions::default())?;
+//!     let mut machine = MachineBuilder::new()
+//!         .limits(RunLimits { max_steps: Some(1_000_000), ..Default::default() })
+//!         .build();
+//!     machine.capture_output();
+//!     machine.close_stdin();
+//!     machine.run(&program)?;
+//!     assert_eq!(machine.take_output(), b"A");
+//!     Ok(())
+//! }
+//! ```
+
+pub use crate::compiler::{CompileOptions, Extension, ParseError, Program, ValidationError};
+pub use crate::engine::{Engine, Interpreter};
+pub use crate::error::BfError;
+pub use crate::input::EofPolicy;
+pub use crate::metrics::Metrics;
+pub use crate::tape::{Tape, VecTape};
+pub use crate::vm::{CancelToken, Machine, MachineBuilder, RunLimits, RunReport, RuntimeError};
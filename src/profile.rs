@@ -0,0 +1,44 @@
+use std::collections::BTreeMap;
+
+use crate::compiler::Program;
+use crate::engine::Engine;
+use crate::tape::Tape;
+use crate::vm::RuntimeError;
+
+/// How often every instruction of a program was executed
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Profile {
+    counts: Vec<u64>,
+}
+
+impl Profile {
+    /// run the program on the engine and count every executed instruction
+    /// the steps of a loop, that the tiered engine optimized, are counted for the start of the loop
+    /// the profile is returned even if the run failed, it then covers everything up to the error
+    pub fn record<T: Tape>(engine: &mut dyn Engine<T>, program: &Program) -> (Profile, Result<(), RuntimeError>) {
+        let mut counts = vec![0; program.len()];
+        engine.load(program);
+        let result = loop {
+            let instr_ptr = engine.instr_ptr();
+            match engine.step() {
+                Ok(true) => counts[instr_ptr] += 1,
+                Ok(false) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+        (Profile { counts }, result)
+    }
+
+    /// how often the instruction at `instr_ptr` was executed
+    pub fn count(&self, instr_ptr: usize) -> u64 {
+        self.counts.get(instr_ptr).copied().unwrap_or(0)
+    }
+
+    /// execution counts of the commands in the source, keyed by line and byte offset inside the line
+    /// only an unoptimized program maps every instruction back to a command
+    pub fn by_source(&self, program: &Program) -> BTreeMap<(usize, usize), u64> {
+        (0..program.len())
+            .filter_map(|instr_ptr| program.span(instr_ptr).map(|pos| ((pos.line, pos.byte), self.count(instr_ptr))))
+            .collect()
+    }
+}
@@ -0,0 +1,133 @@
+use clap::{ArgMatches, ValueEnum};
+use clap::parser::ValueSource;
+use core::fmt::Display;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{Config, compiler::{Extension, Pass}, input::EofPolicy, vm::{OverflowPolicy, PointerPolicy}};
+
+pub const PROJECT_FILE: &str = "bf.toml";
+
+/// Per-project defaults read from a `bf.toml`
+/// the keys are named like the long command line flags
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub cells: Option<usize>,
+    pub ext: Option<Vec<String>>,
+    pub tapes: Option<usize>,
+    pub eof: Option<String>,
+    #[serde(rename = "pointer-bounds")]
+    pub pointer_bounds: Option<String>,
+    pub optimize: Option<bool>,
+    pub passes: Option<Vec<String>>,
+    #[serde(rename = "cell-overflow")]
+    pub cell_overflow: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ProjectConfigError {
+    path: PathBuf,
+    msg: String,
+}
+
+impl Display for ProjectConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error in project config {}:\n{}", self.path.display(), self.msg)
+    }
+}
+
+impl ProjectConfig {
+    /// search for a `bf.toml` in `start` and all of its parent directories
+    pub fn discover(start: &Path) -> Option<PathBuf> {
+        start.ancestors()
+            .map(|dir| dir.join(PROJECT_FILE))
+            .find(|file| file.is_file())
+    }
+
+    pub fn load(path: &Path) -> Result<ProjectConfig, ProjectConfigError> {
+        let error = |msg: String| ProjectConfigError { path: path.to_path_buf(), msg };
+        let contents = fs::read_to_string(path).map_err(|err| error(err.to_string()))?;
+        toml::from_str(&contents).map_err(|err| error(err.to_string()))
+    }
+
+    /// overwrite every option of the config that wasn't given on the command line
+    pub fn apply(self, cnfg: &mut Config, matches: &ArgMatches, path: &Path) -> Result<(), ProjectConfigError> {
+        let error = |msg: String| ProjectConfigError { path: path.to_path_buf(), msg };
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        if let Some(cells) = self.cells.filter(|_| !from_cli("cell_sz")) {
+            cnfg.cell_sz = cells;
+        }
+        if let Some(tapes) = self.tapes.filter(|_| !from_cli("tapes")) {
+            cnfg.tapes = tapes;
+        }
+        if let Some(optimize) = self.optimize.filter(|_| !from_cli("optimize")) {
+            cnfg.optimize = optimize;
+        }
+        if let Some(extensions) = self.ext.filter(|_| !from_cli("extensions")) {
+            cnfg.extensions = extensions.iter()
+                .map(|ext| Extension::from_str(ext, true).map_err(|_| error(format!("unknown extension '{ext}'"))))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(eof) = self.eof.filter(|_| !from_cli("eof")) {
+            cnfg.eof = EofPolicy::from_str(&eof, true).map_err(|_| error(format!("unknown eof policy '{eof}'")))?;
+        }
+        if let Some(bounds) = self.pointer_bounds.filter(|_| !from_cli("pointer_bounds")) {
+            cnfg.pointer_bounds = PointerPolicy::from_str(&bounds, true).map_err(|_| error(format!("unknown pointer policy '{bounds}'")))?;
+        }
+        if let Some(passes) = self.passes.filter(|_| !from_cli("passes")) {
+            cnfg.passes = passes.iter()
+                .map(|pass| Pass::from_str(pass, true).map_err(|_| error(format!("unknown optimizer pass '{pass}'"))))
+                .collect::<Result<_, _>>()?;
+        }
+        // a project file can't turn the overflow checks of `--sandbox` off
+        if let Some(overflow) = self.cell_overflow.filter(|_| !from_cli("cell_overflow") && !cnfg.sandbox) {
+            cnfg.cell_overflow = Some(OverflowPolicy::from_str(&overflow, true).map_err(|_| error(format!("unknown overflow policy '{overflow}'")))?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn apply(toml: &str, args: &[&str]) -> Result<Config, ProjectConfigError> {
+        let matches = Config::command().get_matches_from(["bf", "-i", "+"].iter().chain(args));
+        let mut cnfg = Config::from_arg_matches(&matches).unwrap();
+        let project: ProjectConfig = toml::from_str(toml).unwrap();
+        project.apply(&mut cnfg, &matches, Path::new(PROJECT_FILE))?;
+        Ok(cnfg)
+    }
+
+    #[test]
+    fn overflow_and_passes_come_from_the_project() {
+        let cnfg = apply("cell-overflow = \"error\"\npasses = [\"mul-add\", \"io-batching\"]", &[]).unwrap();
+        assert_eq!(cnfg.overflow_policy(), OverflowPolicy::Error);
+        assert_eq!(cnfg.passes, [Pass::MulAdd, Pass::IoBatching]);
+        assert_eq!(cnfg.compile_options().passes, [Pass::MulAdd, Pass::IoBatching]);
+    }
+
+    #[test]
+    fn command_line_overrides_the_project() {
+        let toml = "cell-overflow = \"error\"\npasses = [\"mul-add\"]";
+        let cnfg = apply(toml, &["--cell-overflow", "wrap", "--passes", "dead-stores,run-once-loops"]).unwrap();
+        assert_eq!(cnfg.overflow_policy(), OverflowPolicy::Wrap);
+        assert_eq!(cnfg.passes, [Pass::DeadStores, Pass::RunOnceLoops]);
+    }
+
+    #[test]
+    fn project_keeps_the_sandbox_checked() {
+        let cnfg = apply("cell-overflow = \"wrap\"", &["--sandbox"]).unwrap();
+        assert_eq!(cnfg.overflow_policy(), OverflowPolicy::Error);
+    }
+
+    #[test]
+    fn unknown_values_are_rejected() {
+        assert!(apply("cell-overflow = \"saturate\"", &[]).is_err());
+        assert!(apply("passes = [\"inline\"]", &[]).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use core::fmt::Display;
+
+use crate::compiler::{CompileOptions, Position};
+use crate::syntax::SourceFile;
+
+/// shorter sequences aren't worth extracting
+const MIN_LEN: usize = 8;
+/// how many of the longest distinct sequences are counted
+const MAX_CANDIDATES: usize = 200;
+/// how many sequences are reported
+const MAX_REPORTED: usize = 10;
+
+/// A sequence of commands, that occurs several times in a program
+#[derive(Debug, PartialEq, Clone)]
+pub struct Repeat {
+    pub commands: String,
+    /// start of every non-overlapping occurrence
+    pub positions: Vec<Position>,
+    /// commands saved by defining the sequence once as a procedure `(...)` and calling it with `:`
+    /// setting up the cell, that selects the procedure, isn't counted
+    pub savings: usize,
+}
+
+impl Display for Repeat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let positions: Vec<String> = self.positions.iter().map(|pos| format!("{}:{}", pos.line, pos.col)).collect();
+        write!(
+            f,
+            "{} commands repeated {} times, saves about {}: {}\n  at {}",
+            self.commands.len(), self.positions.len(), self.savings, self.commands, positions.join(", ")
+        )
+    }
+}
+
+/// The repeated sequences with the biggest savings, best first
+/// only sequences with balanced brackets and more than one kind of command are considered,
+/// sequences contained in a better one are left out
+pub fn find_repeats(source: &str, options: &CompileOptions) -> Vec<Repeat> {
+    let tokens = SourceFile::parse(source, options).tokens;
+    let commands: Vec<u8> = tokens.iter().map(|token| token.command as u8).collect();
+
+    let suffixes = suffix_array(&commands);
+    let mut candidates: Vec<&[u8]> = common_prefixes(&commands, &suffixes).into_iter()
+        .map(|(start, len)| &commands[start..start + balanced_prefix(&commands[start..start + len])])
+        .filter(|candidate| candidate.len() >= MIN_LEN)
+        .filter(|candidate| candidate.iter().any(|&command| command != candidate[0]))
+        .collect();
+    candidates.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+    candidates.dedup();
+    candidates.truncate(MAX_CANDIDATES);
+
+    let mut repeats: Vec<Repeat> = candidates.into_iter()
+        .filter_map(|candidate| {
+            let starts = occurrences(&commands, candidate);
+            let savings = (candidate.len() * starts.len()).checked_sub(candidate.len() + 2 + starts.len())?;
+            (starts.len() > 1 && savings > 0).then(|| Repeat {
+                commands: String::from_utf8_lossy(candidate).into_owned(),
+                positions: starts.into_iter().map(|start| tokens[start].pos).collect(),
+                savings,
+            })
+        })
+        .collect();
+    repeats.sort_by_key(|repeat| std::cmp::Reverse(repeat.savings));
+
+    let mut reported: Vec<Repeat> = Vec::new();
+    for repeat in repeats {
+        if reported.len() == MAX_REPORTED { break; }
+        if !reported.iter().any(|better| better.commands.contains(&repeat.commands)) {
+            reported.push(repeat);
+        }
+    }
+    reported
+}
+
+/// starts of the suffixes in sorted order, by prefix doubling
+fn suffix_array(text: &[u8]) -> Vec<usize> {
+    let n = text.len();
+    let mut suffixes: Vec<usize> = (0..n).collect();
+    let mut rank: Vec<usize> = text.iter().map(|&byte| byte as usize).collect();
+    let mut next = vec![0; n];
+    let mut len = 1;
+    if n < 2 {
+        return suffixes;
+    }
+    loop {
+        let key = |i: usize| (rank[i], rank.get(i + len).map_or(0, |rank| rank + 1));
+        suffixes.sort_by_key(|&i| key(i));
+        next[suffixes[0]] = 0;
+        for pair in 1..n {
+            let step = usize::from(key(suffixes[pair - 1]) != key(suffixes[pair]));
+            next[suffixes[pair]] = next[suffixes[pair - 1]] + step;
+        }
+        std::mem::swap(&mut rank, &mut next);
+        if rank[suffixes[n - 1]] == n - 1 { break; }
+        len *= 2;
+    }
+    suffixes
+}
+
+/// start and length of the common prefix of every suffix with the one before it in sorted order (Kasai)
+fn common_prefixes(text: &[u8], suffixes: &[usize]) -> Vec<(usize, usize)> {
+    let n = text.len();
+    let mut rank = vec![0; n];
+    for (index, &suffix) in suffixes.iter().enumerate() {
+        rank[suffix] = index;
+    }
+
+    let mut prefixes = Vec::new();
+    let mut len = 0;
+    for start in 0..n {
+        if rank[start] == 0 {
+            len = 0;
+            continue;
+        }
+        let previous = suffixes[rank[start] - 1];
+        while start + len < n && previous + len < n && text[start + len] == text[previous + len] {
+            len += 1;
+        }
+        prefixes.push((start, len));
+        len = len.saturating_sub(1);
+    }
+    prefixes
+}
+
+/// length of the longest prefix, in which every loop opened is also closed
+fn balanced_prefix(commands: &[u8]) -> usize {
+    let mut depth = 0usize;
+    let mut longest = 0;
+    for (index, &command) in commands.iter().enumerate() {
+        match command {
+            b'[' => depth += 1,
+            b']' => match depth.checked_sub(1) {
+                Some(shallower) => depth = shallower,
+                None => break,
+            },
+            _ => {},
+        }
+        if depth == 0 {
+            longest = index + 1;
+        }
+    }
+    longest
+}
+
+/// starts of the non-overlapping occurrences of `pattern`
+fn occurrences(text: &[u8], pattern: &[u8]) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut start = 0;
+    while start + pattern.len() <= text.len() {
+        if &text[start..start + pattern.len()] == pattern {
+            starts.push(start);
+            start += pattern.len();
+        } else {
+            start += 1;
+        }
+    }
+    starts
+}
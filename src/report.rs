@@ -0,0 +1,113 @@
+use crate::compiler::Program;
+use crate::loops::{self, LoopNode};
+use crate::profile::Profile;
+
+/// how many of the hottest loops are listed
+const HOT_LOOPS: usize = 10;
+
+/// Self-contained html page of a profiled run
+/// every command of the source is colored by how often it ran (log scale), the hottest loops link to their lines
+pub fn render_report(title: &str, source: &str, program: &Program, profile: &Profile) -> String {
+    let counts = profile.by_source(program);
+    let max = counts.values().copied().max().unwrap_or(0);
+
+    let mut code = String::new();
+    for (index, line) in source.split('\n').enumerate() {
+        let line_nr = index + 1;
+        code.push_str(&format!("<span class=\"line\" id=\"l{line_nr}\"><span class=\"nr\">{line_nr:>4}</span> "));
+        for (byte, ch) in line.char_indices() {
+            let ch = loops::escape_html(&ch.to_string());
+            match counts.get(&(line_nr, byte)) {
+                Some(&count) if count > 0 => code.push_str(&format!(
+                    "<span style=\"background: hsl({:.0}, 90%, 60%)\" title=\"{count}\">{ch}</span>",
+                    240.0 * (1.0 - heat(count, max))
+                )),
+                _ => code.push_str(&ch),
+            }
+        }
+        code.push_str("</span>\n");
+    }
+
+    let tree = loops::loop_tree(program, Some(profile));
+    let mut hot = Vec::new();
+    flatten(&tree, &mut hot);
+    hot.sort_by_key(|node| std::cmp::Reverse(node.iterations));
+    let hot_loops: String = hot.iter()
+        .take(HOT_LOOPS)
+        .filter(|node| node.iterations.is_some_and(|iterations| iterations > 0))
+        .map(|node| format!(
+            "<li><a href=\"#l{}\">{}:{}</a>: {} iterations, body of {} instructions</li>\n",
+            node.open.line, node.open.line, node.open.col, node.iterations.unwrap_or(0), node.body
+        ))
+        .collect();
+
+    let title = loops::escape_html(title);
+    format!("<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Profile of {title}</title>
+<style>
+pre {{ line-height: 1.3; }}
+.nr {{ color: #888; user-select: none; }}
+.line:target {{ outline: 2px solid #333; }}
+</style>
+</head>
+<body>
+<h1>Profile of {title}</h1>
+<p>{} instructions executed, the hottest command ran {max} times. Hover a command for its count.</p>
+<h2>Hot loops</h2>
+<ol>
+{hot_loops}</ol>
+<h2>Source</h2>
+<pre>
+{code}</pre>
+</body>
+</html>
+", counts.values().sum::<u64>())
+}
+
+/// 256-color codes of the terminal heatmap, from cold to hot
+const HEAT_COLORS: [u8; 6] = [27, 39, 48, 190, 214, 196];
+
+/// The source with every command colored by how often it ran (log scale), for the terminal
+/// commands that never ran and comments are dimmed
+pub fn render_heatmap(source: &str, program: &Program, profile: &Profile) -> String {
+    let counts = profile.by_source(program);
+    let max = counts.values().copied().max().unwrap_or(0);
+
+    let mut heatmap = String::new();
+    for (index, line) in source.lines().enumerate() {
+        for (byte, ch) in line.char_indices() {
+            match counts.get(&(index + 1, byte)) {
+                Some(&count) if count > 0 => {
+                    let color = HEAT_COLORS[(heat(count, max) * (HEAT_COLORS.len() - 1) as f64).round() as usize];
+                    heatmap.push_str(&format!("\x1b[38;5;{color}m{ch}\x1b[0m"));
+                },
+                _ => heatmap.push_str(&format!("\x1b[2m{ch}\x1b[0m")),
+            }
+        }
+        heatmap.push('\n');
+    }
+    heatmap.push_str("cold ");
+    for color in HEAT_COLORS {
+        heatmap.push_str(&format!("\x1b[38;5;{color}m#\x1b[0m"));
+    }
+    heatmap.push_str(&format!(" hot, the hottest command ran {max} times"));
+    heatmap
+}
+
+/// position of `count` between 1 and `max` on a log scale, from 0.0 to 1.0
+pub(crate) fn heat(count: u64, max: u64) -> f64 {
+    if max <= 1 {
+        return 1.0;
+    }
+    (count.max(1) as f64).ln() / (max as f64).ln()
+}
+
+fn flatten<'a>(nodes: &'a [LoopNode], flat: &mut Vec<&'a LoopNode>) {
+    for node in nodes {
+        flat.push(node);
+        flatten(&node.children, flat);
+    }
+}
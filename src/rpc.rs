@@ -0,0 +1,327 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::time::Instant;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::compiler::{Extension, Program};
+use crate::diagnostic::Diagnostic;
+use crate::exporter::ServiceMetrics;
+use crate::failure::Failure;
+use crate::vm::{Machine, MachineBuilder};
+use crate::Config;
+
+/// methods, that are known to the session, the metrics use them as labels
+const METHODS: [&str; 5] = ["compile", "run", "step", "inspectTape", "setBreakpoint"];
+
+/// error codes of the JSON-RPC spec
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// the program didn't compile
+const COMPILE_ERROR: i64 = -32000;
+/// `run`, `step` or `inspectTape` before a successful `compile`
+const NO_PROGRAM: i64 = -32001;
+/// the breakpoint doesn't point at a command
+const NO_COMMAND: i64 = -32002;
+
+/// cells returned by `inspectTape`, if no length is given
+const DEFAULT_TAPE_WINDOW: usize = 16;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct CompileParams {
+    source: String,
+    #[serde(default)]
+    optimize: bool,
+    #[serde(default)]
+    extensions: Vec<String>,
+    /// bytes read by `,`, the service never reads its own stdin for the program
+    #[serde(default)]
+    input: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct RunParams {
+    max_steps: Option<u64>,
+    #[serde(default)]
+    input: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct StepParams {
+    count: Option<u64>,
+    #[serde(default)]
+    input: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct InspectParams {
+    start: Option<usize>,
+    len: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct BreakpointParams {
+    line: usize,
+    col: usize,
+    #[serde(default = "enabled")]
+    enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+/// an error response, code and message
+type RpcError = (i64, String);
+
+/// A compiled program with the machine running it
+struct Loaded {
+    program: Program,
+    machine: Machine,
+    instr_ptr: usize,
+    finished: bool,
+    /// how the program failed, if it did
+    failure: Option<Failure>,
+}
+
+/// State of a JSON-RPC session, one program at a time
+/// the machine is created with the options of the command line, `compile` replaces it
+pub struct Session<'a> {
+    cnfg: &'a Config,
+    loaded: Option<Loaded>,
+    breakpoints: BTreeSet<usize>,
+    metrics: Option<ServiceMetrics>,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(cnfg: &'a Config) -> Self {
+        Session { cnfg, loaded: None, breakpoints: BTreeSet::new(), metrics: None }
+    }
+
+    /// count requests, executions and failures of the session in `metrics`
+    pub fn with_metrics(mut self, metrics: ServiceMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Answer requests, one JSON object per line, until the input ends
+    /// notifications (requests without an id) get no response
+    pub fn serve(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() { continue; }
+            if let Some(response) = self.handle(&line) {
+                writeln!(output, "{response}")?;
+                output.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the response to a single message
+    pub fn handle(&mut self, message: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(message) {
+            Ok(request) => request,
+            Err(err) => return Some(error_response(Value::Null, (PARSE_ERROR, err.to_string()))),
+        };
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return Some(error_response(id.unwrap_or(Value::Null), (INVALID_REQUEST, String::from("missing method"))));
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        let started = Instant::now();
+        let before = self.loaded.as_ref().map(|loaded| (loaded.machine.metrics().steps, loaded.finished));
+        let result = match method {
+            "compile" => parse_params(params).and_then(|params| self.compile(params)),
+            "run" => parse_params(params).and_then(|params| self.run(params)),
+            "step" => parse_params(params).and_then(|params| self.step(params)),
+            "inspectTape" => parse_params(params).and_then(|params| self.inspect_tape(params)),
+            "setBreakpoint" => parse_params(params).and_then(|params| self.set_breakpoint(params)),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{method}'"))),
+        };
+        if let Some(metrics) = &self.metrics {
+            self.record(metrics, method, before, &result);
+            let method = METHODS.into_iter().find(|&known| known == method).unwrap_or("unknown");
+            metrics.observe_request(method, started.elapsed());
+        }
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(err) => error_response(id, err),
+        })
+    }
+
+    /// steps and executions of a `run` or `step` and failed requests
+    fn record(&self, metrics: &ServiceMetrics, method: &str, before: Option<(u64, bool)>, result: &Result<Value, RpcError>) {
+        match result {
+            Err((COMPILE_ERROR, _)) => metrics.record_failure(Failure::Parse),
+            Err(_) => metrics.record_failure(Failure::Usage),
+            Ok(_) => {},
+        }
+        if !matches!(method, "run" | "step") { return; }
+        if let (Some(loaded), Some((steps, finished))) = (&self.loaded, before) {
+            metrics.add_steps(loaded.machine.metrics().steps - steps);
+            if loaded.finished && !finished {
+                metrics.record_execution(loaded.failure);
+            }
+        }
+    }
+
+    fn compile(&mut self, params: CompileParams) -> Result<Value, RpcError> {
+        let mut options = self.cnfg.compile_options();
+        options.optimize = params.optimize;
+        for name in &params.extensions {
+            let extension = <Extension as clap::ValueEnum>::from_str(name, true)
+                .map_err(|_| (INVALID_PARAMS, format!("unknown extension '{name}'")))?;
+            options.extensions.push(extension);
+        }
+
+        let program = Program::compile(&params.source, &options).map_err(|err| (COMPILE_ERROR, err.to_string()))?;
+        let warnings: Vec<Value> = program.warnings().iter().map(diagnostic_json).collect();
+        let mut machine = MachineBuilder::from_config(self.cnfg).input(params.input.as_bytes()).build();
+        machine.close_stdin();
+        machine.capture_output();
+
+        let instructions = program.len();
+        self.loaded = Some(Loaded { program, machine, instr_ptr: 0, finished: false, failure: None });
+        self.breakpoints.clear();
+        Ok(json!({ "instructions": instructions, "warnings": warnings }))
+    }
+
+    /// run until the program ends, a breakpoint is reached or `maxSteps` steps were taken
+    fn run(&mut self, params: RunParams) -> Result<Value, RpcError> {
+        let breakpoints = &self.breakpoints;
+        let loaded = self.loaded.as_mut().ok_or_else(no_program)?;
+        loaded.machine.feed_input(params.input.as_bytes());
+        // the breakpoint the previous run stopped at shouldn't stop this one right away
+        let mut first = true;
+        let mut taken = 0u64;
+        let stop = loop {
+            if loaded.finished {
+                break "finished";
+            }
+            if !first && breakpoints.contains(&loaded.instr_ptr) {
+                break "breakpoint";
+            }
+            if params.max_steps.is_some_and(|max| taken >= max) {
+                break "paused";
+            }
+            first = false;
+            taken += 1;
+            if let Err(err) = loaded.advance() {
+                return Ok(loaded.state("error", Some(err)));
+            }
+        };
+        Ok(loaded.state(stop, None))
+    }
+
+    fn step(&mut self, params: StepParams) -> Result<Value, RpcError> {
+        let loaded = self.loaded.as_mut().ok_or_else(no_program)?;
+        loaded.machine.feed_input(params.input.as_bytes());
+        for _ in 0..params.count.unwrap_or(1) {
+            if loaded.finished { break; }
+            if let Err(err) = loaded.advance() {
+                return Ok(loaded.state("error", Some(err)));
+            }
+        }
+        let stop = if loaded.finished { "finished" } else { "paused" };
+        Ok(loaded.state(stop, None))
+    }
+
+    fn inspect_tape(&mut self, params: InspectParams) -> Result<Value, RpcError> {
+        let loaded = self.loaded.as_ref().ok_or_else(no_program)?;
+        let snapshot = loaded.machine.snapshot();
+        let start = params.start.unwrap_or(snapshot.ptr.saturating_sub(DEFAULT_TAPE_WINDOW / 2)).min(snapshot.cells.len());
+        let end = start.saturating_add(params.len.unwrap_or(DEFAULT_TAPE_WINDOW)).min(snapshot.cells.len());
+        Ok(json!({ "ptr": snapshot.ptr, "tape": loaded.machine.tape(), "start": start, "cells": &snapshot.cells[start..end] }))
+    }
+
+    /// breakpoints are set on the command at a source position and only work for unoptimized programs
+    fn set_breakpoint(&mut self, params: BreakpointParams) -> Result<Value, RpcError> {
+        let loaded = self.loaded.as_ref().ok_or_else(no_program)?;
+        let instr_ptr = (0..loaded.program.len())
+            .find(|&instr_ptr| loaded.program.span(instr_ptr).is_some_and(|pos| pos.line == params.line && pos.col == params.col))
+            .ok_or_else(|| (NO_COMMAND, format!("no command at {}:{}, is the program optimized?", params.line, params.col)))?;
+        if params.enabled {
+            self.breakpoints.insert(instr_ptr);
+        } else {
+            self.breakpoints.remove(&instr_ptr);
+        }
+        Ok(json!({ "instruction": instr_ptr, "enabled": params.enabled }))
+    }
+}
+
+impl Loaded {
+    /// execute a single instruction
+    fn advance(&mut self) -> Result<(), String> {
+        let result = match self.machine.step(&self.program, self.instr_ptr) {
+            Ok(Some(next)) => {
+                self.instr_ptr = next;
+                Ok(())
+            },
+            Ok(None) => {
+                self.finished = true;
+                self.machine.finish()
+            },
+            Err(err) => Err(err),
+        };
+        result.map_err(|err| {
+            self.finished = true;
+            self.failure = Some(err.failure());
+            err.to_string()
+        })
+    }
+
+    /// where the machine stopped and the output since the last call
+    fn state(&mut self, stop: &str, error: Option<String>) -> Value {
+        let output: String = self.machine.take_output().into_iter().map(char::from).collect();
+        let pos = self.program.span(self.instr_ptr).map(|pos| json!({ "line": pos.line, "col": pos.col }));
+        json!({
+            "state": stop,
+            "error": error,
+            "output": output,
+            "instruction": self.instr_ptr,
+            "position": pos,
+            "ptr": self.machine.ptr(),
+            "value": self.machine.value(),
+            "steps": self.machine.metrics().steps,
+        })
+    }
+}
+
+/// missing params are treated like an empty object, so methods without required params can leave them out
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|err| (INVALID_PARAMS, err.to_string()))
+}
+
+fn no_program() -> RpcError {
+    (NO_PROGRAM, String::from("no program compiled yet, call compile first"))
+}
+
+fn error_response(id: Value, (code, message): RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn diagnostic_json(diagnostic: &Diagnostic) -> Value {
+    json!({ "line": diagnostic.pos.line, "col": diagnostic.pos.col, "message": diagnostic.message })
+}
+
+/// Answer JSON-RPC requests on stdin until it is closed
+pub fn serve_stdio(cnfg: &Config, metrics: Option<ServiceMetrics>) -> io::Result<()> {
+    let mut session = Session::new(cnfg);
+    if let Some(metrics) = metrics {
+        session = session.with_metrics(metrics);
+    }
+    session.serve(io::stdin().lock(), io::stdout().lock())
+}
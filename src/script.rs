@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+
+use crate::input::parse_escaped;
+
+/// A single line of an input script
+#[derive(Debug, PartialEq, Clone)]
+pub enum Directive {
+    /// `send "abc"`: make the bytes available to `,`
+    Send(Vec<u8>),
+    /// `expect "prompt>"`: wait until the program printed the text
+    Expect(Vec<u8>),
+    /// `wait-steps 1000`: hold back the following directives, until the program ran that many steps since it last read input
+    WaitSteps(u64),
+}
+
+/// Scripted input, to drive interactive programs deterministically
+/// the expected texts are matched in order while the program prints, and the script is evaluated
+/// whenever the program reads input: `send` queues bytes, an `expect` before it must already be matched
+/// and a `wait-steps` must have passed (otherwise the program would wait for input that never arrives)
+#[derive(Debug, PartialEq, Clone)]
+pub struct InputScript {
+    directives: VecDeque<Directive>,
+    pending: VecDeque<u8>,
+    /// the directives before this index are matched, if they are an `expect`
+    matched: usize,
+    /// the end of the output, that the next `expect` could still match
+    output: Vec<u8>,
+    /// steps when the program last read input
+    last_read: u64,
+}
+
+impl InputScript {
+    /// parse a script with one directive per line
+    /// empty lines and lines starting with `#` are ignored
+    pub fn parse(script: &str) -> Result<InputScript, String> {
+        let mut directives = VecDeque::new();
+
+        for (index, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+
+            let error = |msg: String| format!("Error in input script at line {}: {msg}", index + 1);
+            let (name, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let arg = arg.trim();
+            let directive = match name {
+                "send" => Directive::Send(parse_string(arg).map_err(error)?),
+                "expect" => Directive::Expect(parse_string(arg).map_err(error)?),
+                "wait-steps" => Directive::WaitSteps(arg.parse().map_err(|_| error(format!("'{arg}' is not a step count")))?),
+                _ => return Err(error(format!("unknown directive '{name}'"))),
+            };
+            directives.push_back(directive);
+        }
+
+        let mut script = InputScript { directives, pending: VecDeque::new(), matched: 0, output: Vec::new(), last_read: 0 };
+        script.match_output();
+        Ok(script)
+    }
+
+    /// hook for the write path of the machine, matches the output against the next `expect`
+    pub fn observe_output(&mut self, byte: u8) {
+        self.output.push(byte);
+        self.match_output();
+    }
+
+    /// match as many `expect`s as possible, only the output that the next one could still match is kept
+    fn match_output(&mut self) {
+        while let Some((index, expected)) = self.next_expect() {
+            match find(&self.output, expected) {
+                Some(end) => {
+                    // every expect only looks at the output after the previous match
+                    self.output.drain(..end);
+                    self.matched = index + 1;
+                },
+                None => {
+                    let keep = expected.len() - 1;
+                    if self.output.len() > keep {
+                        self.output.drain(..self.output.len() - keep);
+                    }
+                    return;
+                },
+            }
+        }
+        self.output.clear();
+    }
+
+    /// the next `expect`, that isn't matched yet, with its index
+    fn next_expect(&self) -> Option<(usize, &[u8])> {
+        self.directives.iter().enumerate().skip(self.matched).find_map(|(index, directive)| match directive {
+            Directive::Expect(expected) => Some((index, expected.as_slice())),
+            _ => None,
+        })
+    }
+
+    /// read the next byte, `None` if the script has no more input
+    /// fails if the program asks for input while the script still expects output or holds the input back
+    pub fn read_byte(&mut self, steps: u64) -> Result<Option<u8>, String> {
+        loop {
+            if let Some(byte) = self.pending.pop_front() {
+                self.last_read = steps;
+                return Ok(Some(byte));
+            }
+
+            match self.directives.front() {
+                None => return Ok(None),
+                Some(Directive::Send(bytes)) => self.pending.extend(bytes),
+                Some(Directive::Expect(expected)) if self.matched == 0 => {
+                    return Err(format!(
+                        "Program asked for input after {steps} steps, but the script still expects \"{}\"",
+                        String::from_utf8_lossy(expected)
+                        ));
+                },
+                Some(Directive::Expect(_)) => {},
+                Some(Directive::WaitSteps(wait)) => {
+                    let ready = self.last_read.saturating_add(*wait);
+                    if steps < ready {
+                        return Err(format!("Program asked for input after {steps} steps, but the script holds it back until step {ready}"));
+                    }
+                },
+            }
+            self.directives.pop_front();
+            self.matched = self.matched.saturating_sub(1);
+        }
+    }
+
+    /// fails if the program finished before every expected output was seen
+    pub fn check_finished(&self) -> Result<(), String> {
+        match self.next_expect() {
+            Some((_, expected)) => Err(format!("Program finished, but the script still expects \"{}\"", String::from_utf8_lossy(expected))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// parse a double quoted string with escapes
+fn parse_string(arg: &str) -> Result<Vec<u8>, String> {
+    let inner = arg.strip_prefix('"')
+        .and_then(|arg| arg.strip_suffix('"'))
+        .filter(|_| arg.len() >= 2)
+        .ok_or_else(|| format!("expected a double quoted string, found '{arg}'"))?;
+    Ok(parse_escaped(inner)?.0)
+}
+
+/// position after the first occurrence of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len())
+        .position(|window| window == needle)
+        .map(|pos| pos + needle.len())
+}
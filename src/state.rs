@@ -0,0 +1,84 @@
+use core::fmt::Display;
+
+/// Snapshot of a Machine at one point of the execution
+#[derive(Debug, PartialEq, Clone)]
+pub struct MachineState {
+    pub cells: Vec<u8>,
+    pub ptr: usize,
+    pub steps: u64,
+}
+
+/// A single cell that differs between two snapshots
+#[derive(Debug, PartialEq, Clone)]
+pub struct CellChange {
+    pub index: usize,
+    pub before: u8,
+    pub after: u8,
+}
+
+/// Difference between two snapshots, as produced by `MachineState::diff`
+#[derive(Debug, PartialEq, Clone)]
+pub struct TapeDiff {
+    pub changes: Vec<CellChange>,
+    pub ptr_before: usize,
+    pub ptr_after: usize,
+    pub step_delta: i64,
+}
+
+impl MachineState {
+    /// Compare this snapshot to a later one
+    /// cells missing in one of the tapes are treated as 0
+    pub fn diff(&self, other: &MachineState) -> TapeDiff {
+        let len = self.cells.len().max(other.cells.len());
+        let changes = (0..len)
+            .map(|index| CellChange {
+                index,
+                before: self.cells.get(index).copied().unwrap_or(0),
+                after: other.cells.get(index).copied().unwrap_or(0),
+            })
+            .filter(|change| change.before != change.after)
+            .collect();
+
+        TapeDiff {
+            changes,
+            ptr_before: self.ptr,
+            ptr_after: other.ptr,
+            step_delta: other.steps as i64 - self.steps as i64,
+        }
+    }
+}
+
+impl TapeDiff {
+    /// true if neither the tape nor the pointer changed
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.ptr_before == self.ptr_after
+    }
+
+    /// net movement of the pointer, negative if it moved left
+    pub fn ptr_movement(&self) -> isize {
+        self.ptr_after as isize - self.ptr_before as isize
+    }
+}
+
+impl Display for TapeDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "steps: {:+}", self.step_delta)?;
+        if self.ptr_before == self.ptr_after {
+            writeln!(f, "ptr: {} (unchanged)", self.ptr_after)?;
+        } else {
+            writeln!(f, "ptr: {} -> {} ({:+})", self.ptr_before, self.ptr_after, self.ptr_movement())?;
+        }
+
+        if self.changes.is_empty() {
+            return write!(f, "no cells changed");
+        }
+        for (i, change) in self.changes.iter().enumerate() {
+            let delta = change.after as i16 - change.before as i16;
+            write!(f, "cell {}: {} -> {} ({:+})", change.index, change.before, change.after, delta)?;
+            if i + 1 < self.changes.len() {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use core::fmt::Display;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::compiler::Instruction;
+
+/// Count of every kind of instruction in a program
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Histogram {
+    counts: BTreeMap<&'static str, usize>,
+}
+
+impl Histogram {
+    pub fn of(instructions: &[Instruction]) -> Self {
+        let mut counts = BTreeMap::new();
+        for instr in instructions {
+            *counts.entry(instr.name()).or_insert(0) += 1;
+        }
+        Histogram { counts }
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    pub fn count(&self, name: &str) -> usize {
+        self.counts.get(name).copied().unwrap_or(0)
+    }
+}
+
+/// Instruction counts before and after one optimizer pass
+#[derive(Debug, PartialEq, Clone)]
+pub struct PassStats {
+    pub name: &'static str,
+    pub before: Histogram,
+    pub after: Histogram,
+}
+
+/// What the compiler produced, reported by `--compile-stats`
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CompileStats {
+    pub parsed: Histogram,
+    pub passes: Vec<PassStats>,
+}
+
+impl CompileStats {
+    pub fn new(instructions: &[Instruction]) -> Self {
+        CompileStats { parsed: Histogram::of(instructions), passes: Vec::new() }
+    }
+
+    pub fn record(&mut self, name: &'static str, before: Histogram, after: Histogram) {
+        self.passes.push(PassStats { name, before, after });
+    }
+
+    /// the histogram of the final program
+    pub fn result(&self) -> &Histogram {
+        self.passes.last().map_or(&self.parsed, |pass| &pass.after)
+    }
+}
+
+impl Display for CompileStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "parsed: {} instructions", self.parsed.total())?;
+        for (name, count) in &self.parsed.counts {
+            writeln!(f, "  {name:<10} {count}")?;
+        }
+
+        for pass in &self.passes {
+            writeln!(f, "pass {}: {} -> {} instructions", pass.name, pass.before.total(), pass.after.total())?;
+            let names: BTreeSet<_> = pass.before.counts.keys().chain(pass.after.counts.keys()).collect();
+            for name in names {
+                let (before, after) = (pass.before.count(name), pass.after.count(name));
+                // only the instructions changed by the pass
+                if before != after {
+                    writeln!(f, "  {name:<10} {before} -> {after}")?;
+                }
+            }
+        }
+
+        let total = self.result().total();
+        write!(f, "final size: {} instructions ({} bytes)", total, total * std::mem::size_of::<Instruction>())
+    }
+}
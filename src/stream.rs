@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_core::Stream;
+
+use crate::compiler::Instruction;
+use crate::tape::{Tape, VecTape};
+use crate::vm::{Machine, RuntimeError};
+
+/// Something that happened while the machine ran
+#[derive(Debug)]
+pub enum VmEvent {
+    /// the instruction at `instr_ptr` was executed, `ptr` and `value` are the state after it
+    Step { instr_ptr: usize, ptr: usize, value: u8 },
+    /// a byte printed by `.`
+    Output(u8),
+    /// the program ended, the last event of the stream
+    Finished(Result<(), RuntimeError>),
+}
+
+/// The execution of a program as a `Stream` of events
+/// every poll executes one instruction and respects the task budget of tokio, so the stream doesn't block other tasks
+/// the output is captured and only reported as events, input should be fed up front with `close_stdin` called
+pub struct EventStream<T: Tape = VecTape> {
+    machine: Machine<T>,
+    instructions: Vec<Instruction>,
+    instr_ptr: usize,
+    pending: VecDeque<VmEvent>,
+    done: bool,
+}
+
+impl<T: Tape> EventStream<T> {
+    pub fn new(mut machine: Machine<T>, program: &[Instruction]) -> Self {
+        machine.capture_output();
+        EventStream { machine, instructions: program.to_vec(), instr_ptr: 0, pending: VecDeque::new(), done: false }
+    }
+
+    /// the machine, e.g. to dump the tape once the stream ended
+    pub fn into_machine(self) -> Machine<T> {
+        self.machine
+    }
+}
+
+impl<T: Tape + Unpin> Stream for EventStream<T> {
+    type Item = VmEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<VmEvent>> {
+        let this = self.get_mut();
+        if let Some(event) = this.pending.pop_front() {
+            return Poll::Ready(Some(event));
+        }
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        let coop = ready!(tokio::task::coop::poll_proceed(cx));
+        coop.made_progress();
+        let instr_ptr = this.instr_ptr;
+        let event = match this.machine.step(&this.instructions, instr_ptr) {
+            Ok(Some(next)) => {
+                this.instr_ptr = next;
+                VmEvent::Step { instr_ptr, ptr: this.machine.ptr(), value: this.machine.value() }
+            },
+            Ok(None) => {
+                this.done = true;
+                VmEvent::Finished(this.machine.finish())
+            },
+            Err(err) => {
+                this.done = true;
+                VmEvent::Finished(Err(err))
+            },
+        };
+        this.pending.push_back(event);
+        this.pending.extend(this.machine.take_output().into_iter().map(VmEvent::Output));
+        Poll::Ready(this.pending.pop_front())
+    }
+}
@@ -0,0 +1,60 @@
+use core::fmt::Display;
+
+use crate::compiler::{CompileOptions, Position};
+
+/// A command together with the comments and whitespace in front of it
+#[derive(Debug, PartialEq, Clone)]
+pub struct SyntaxToken {
+    pub command: char,
+    pub pos: Position,
+    /// everything between the previous command and this one
+    pub trivia: String,
+}
+
+/// Lossless parse of a bf program, that keeps comments and whitespace as trivia
+/// printing it reproduces the source exactly, so tools can rewrite the commands without losing the documentation
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SourceFile {
+    pub tokens: Vec<SyntaxToken>,
+    /// everything after the last command
+    pub trailing_trivia: String,
+}
+
+impl SourceFile {
+    pub fn parse(program: &str, options: &CompileOptions) -> Self {
+        let mut tokens = Vec::new();
+        let mut trivia = String::new();
+        let mut pos = Position { line: 1, col: 1, byte: 0 };
+
+        for ch in program.chars() {
+            if options.is_command(ch) {
+                tokens.push(SyntaxToken { command: ch, pos, trivia: std::mem::take(&mut trivia) });
+            } else {
+                trivia.push(ch);
+            }
+
+            if ch == '\n' {
+                pos = Position { line: pos.line + 1, col: 1, byte: 0 };
+            } else {
+                pos.col = Position::advance_col(pos.col, ch, options.tab_width);
+                pos.byte += ch.len_utf8();
+            }
+        }
+
+        SourceFile { tokens, trailing_trivia: trivia }
+    }
+
+    /// only the commands, without any trivia
+    pub fn commands(&self) -> String {
+        self.tokens.iter().map(|token| token.command).collect()
+    }
+}
+
+impl Display for SourceFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for token in &self.tokens {
+            write!(f, "{}{}", token.trivia, token.command)?;
+        }
+        write!(f, "{}", self.trailing_trivia)
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+/// Storage for the cells of a single tape together with its pointer
+/// the machine checks the bounds before moving, so `move_by` never leaves `0..len`
+pub trait Tape {
+    /// amount of addressable cells
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// position of the pointer
+    fn ptr(&self) -> usize;
+
+    /// move the pointer by `offset` cells
+    fn move_by(&mut self, offset: isize);
+
+    /// value of the cell at `index`, that has to be below `len`
+    fn get(&self, index: usize) -> u8;
+
+    fn set(&mut self, index: usize, value: u8);
+}
+
+/// Dense tape, all cells are allocated up front
+pub struct VecTape {
+    cells: Vec<u8>,
+    ptr: usize,
+}
+
+impl VecTape {
+    pub fn new(len: usize) -> Self {
+        VecTape { cells: vec![0; len], ptr: 0 }
+    }
+}
+
+impl Tape for VecTape {
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn ptr(&self) -> usize {
+        self.ptr
+    }
+
+    fn move_by(&mut self, offset: isize) {
+        self.ptr = self.ptr.wrapping_add_signed(offset);
+    }
+
+    fn get(&self, index: usize) -> u8 {
+        self.cells[index]
+    }
+
+    fn set(&mut self, index: usize, value: u8) {
+        self.cells[index] = value;
+    }
+}
+
+/// Tape with a size known at compile time, that doesn't allocate
+pub struct ArrayTape<const N: usize> {
+    cells: [u8; N],
+    ptr: usize,
+}
+
+impl<const N: usize> ArrayTape<N> {
+    pub fn new() -> Self {
+        ArrayTape { cells: [0; N], ptr: 0 }
+    }
+}
+
+impl<const N: usize> Default for ArrayTape<N> {
+    fn default() -> Self {
+        ArrayTape::new()
+    }
+}
+
+impl<const N: usize> Tape for ArrayTape<N> {
+    fn len(&self) -> usize {
+        N
+    }
+
+    fn ptr(&self) -> usize {
+        self.ptr
+    }
+
+    fn move_by(&mut self, offset: isize) {
+        self.ptr = self.ptr.wrapping_add_signed(offset);
+    }
+
+    fn get(&self, index: usize) -> u8 {
+        self.cells[index]
+    }
+
+    fn set(&mut self, index: usize, value: u8) {
+        self.cells[index] = value;
+    }
+}
+
+/// Tape that only stores the non-zero cells, for huge tapes that are mostly empty
+pub struct SparseTape {
+    cells: HashMap<usize, u8>,
+    len: usize,
+    ptr: usize,
+}
+
+impl SparseTape {
+    pub fn new(len: usize) -> Self {
+        SparseTape { cells: HashMap::new(), len, ptr: 0 }
+    }
+}
+
+impl Tape for SparseTape {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn ptr(&self) -> usize {
+        self.ptr
+    }
+
+    fn move_by(&mut self, offset: isize) {
+        self.ptr = self.ptr.wrapping_add_signed(offset);
+    }
+
+    fn get(&self, index: usize) -> u8 {
+        self.cells.get(&index).copied().unwrap_or(0)
+    }
+
+    fn set(&mut self, index: usize, value: u8) {
+        // zero is the default, so it doesn't need an entry
+        if value == 0 {
+            self.cells.remove(&index);
+        } else {
+            self.cells.insert(index, value);
+        }
+    }
+}
+
+/// Tape in memory mapped pages, that the os only backs once they are touched
+/// mapped from a file, the tape survives the process
+#[cfg(feature = "mmap")]
+pub struct MmapTape {
+    cells: memmap2::MmapMut,
+    ptr: usize,
+}
+
+#[cfg(feature = "mmap")]
+impl MmapTape {
+    /// anonymous mapping of `len` zeroed cells
+    pub fn new(len: usize) -> std::io::Result<Self> {
+        Ok(MmapTape { cells: memmap2::MmapMut::map_anon(len)?, ptr: 0 })
+    }
+
+    /// map the cells to `file`, which is grown to `len` bytes if it is shorter
+    pub fn with_file(file: &std::fs::File, len: usize) -> std::io::Result<Self> {
+        if file.metadata()?.len() < len as u64 {
+            file.set_len(len as u64)?;
+        }
+        // SAFETY: the mapping is only sound as long as no one else modifies the file,
+        // which the caller has to guarantee just like for any other mapped file
+        let cells = unsafe { memmap2::MmapOptions::new().len(len).map_mut(file)? };
+        Ok(MmapTape { cells, ptr: 0 })
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Tape for MmapTape {
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn ptr(&self) -> usize {
+        self.ptr
+    }
+
+    fn move_by(&mut self, offset: isize) {
+        self.ptr = self.ptr.wrapping_add_signed(offset);
+    }
+
+    fn get(&self, index: usize) -> u8 {
+        self.cells[index]
+    }
+
+    fn set(&mut self, index: usize, value: u8) {
+        self.cells[index] = value;
+    }
+}
+
+/// Dump only the non-zero cells as `index: value` runs
+/// short zero stretches inside a run are kept, longer ones start a new run
+pub(crate) fn dump_nonzero(tape: &impl Tape) -> String {
+    const MAX_GAP: usize = 2;
+
+    let mut dump = String::new();
+    let mut run_end: Option<usize> = None;
+    for index in 0..tape.len() {
+        let cell = tape.get(index);
+        if cell == 0 { continue; }
+        match run_end {
+            Some(end) if index - end <= MAX_GAP + 1 => {
+                for _ in end + 1..index { dump.push_str(" 0"); }
+            },
+            Some(_) => dump.push_str(&format!("\n{index}:")),
+            None => dump.push_str(&format!("{index}:")),
+        }
+        dump.push_str(&format!(" {cell}"));
+        run_end = Some(index);
+    }
+
+    if run_end.is_none() {
+        dump.push_str("all cells are zero");
+    }
+    dump.push_str(&format!("\nptr: {}", tape.ptr()));
+    dump
+}
@@ -0,0 +1,56 @@
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+
+const PROGRAM_EXTENSIONS: [&str; 2] = ["b", "bf"];
+const COMPRESSED_EXTENSIONS: [&str; 2] = ["gz", "zst"];
+
+/// true if the target should be expanded into several program files
+/// that is the case for directories and glob patterns
+pub fn is_multi_target(target: &str) -> bool {
+    Path::new(target).is_dir() || target.contains(['*', '?', '['])
+}
+
+/// expand a directory or glob pattern into a sorted list of program files
+/// directories are searched recursively for `*.b` and `*.bf` files (optionally compressed)
+pub fn expand(target: &str) -> Result<Vec<PathBuf>, io::Error> {
+    let mut files = Vec::new();
+
+    if Path::new(target).is_dir() {
+        collect_programs(Path::new(target), &mut files)?;
+    } else {
+        let paths = glob::glob(target)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid glob pattern: {err}")))?;
+        for path in paths {
+            let path = path.map_err(io::Error::from)?;
+            if path.is_dir() {
+                collect_programs(&path, &mut files)?;
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn collect_programs(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_programs(&path, files)?;
+        } else if is_program_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_program_file(path: &Path) -> bool {
+    let mut path = path.to_path_buf();
+    if path.extension().is_some_and(|ext| COMPRESSED_EXTENSIONS.iter().any(|comp| ext == *comp)) {
+        path.set_extension("");
+    }
+    path.extension().is_some_and(|ext| PROGRAM_EXTENSIONS.iter().any(|prog| ext == *prog))
+}
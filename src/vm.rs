@@ -1,11 +1,46 @@
 use core::fmt::Display;
-use std::io::Read;
+use std::io::Write;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::{Config, compiler::{Instruction, Program}};
+use clap::ValueEnum;
 
+use crate::{Config, failure::Failure, metrics::{LiveMetrics, Metrics}, compiler::{validate_instructions, Instruction, Program, ValidationError}, input::{EofPolicy, Input, InputError}, script::InputScript, state::MachineState, tape::{self, Tape, VecTape}};
+
+#[derive(Debug)]
 pub enum RuntimeError {
     CellOverflow(String),
     CellUnderflow(String),
+    ProtectedWrite(String),
+    InputScript(String),
+    /// the cancel token was triggered, holds the state at that point
+    Cancelled(Box<MachineState>),
+    /// one of the `RunLimits` was exceeded
+    LimitExceeded(String),
+    /// a cell went past 255 or below 0 with `OverflowPolicy::Error`
+    ValueOverflow(String),
+    /// the instructions are inconsistent, e.g. a jump leads outside of the program
+    CorruptProgram(String),
+    /// reading the input or writing the output failed, as opposed to the input ending
+    Io(std::io::Error),
+}
+
+impl RuntimeError {
+    pub fn failure(&self) -> Failure {
+        match self {
+            RuntimeError::CellOverflow(_) => Failure::Runtime,
+            RuntimeError::CellUnderflow(_) => Failure::Runtime,
+            RuntimeError::ProtectedWrite(_) => Failure::Runtime,
+            RuntimeError::InputScript(_) => Failure::Runtime,
+            RuntimeError::Cancelled(_) => Failure::Runtime,
+            RuntimeError::LimitExceeded(_) => Failure::Limit,
+            RuntimeError::ValueOverflow(_) => Failure::Runtime,
+            RuntimeError::CorruptProgram(_) => Failure::Runtime,
+            RuntimeError::Io(_) => Failure::Io,
+        }
+    }
 }
 
 impl Display for RuntimeError {
@@ -13,118 +48,711 @@ impl Display for RuntimeError {
         match self {
             RuntimeError::CellOverflow(str) => write!(f, "CellOverflow Error: {}", str),
             RuntimeError::CellUnderflow(str) => write!(f, "CellUnderflow Error: {}", str),
+            RuntimeError::ProtectedWrite(str) => write!(f, "ProtectedWrite Error: {}", str),
+            RuntimeError::InputScript(str) => write!(f, "InputScript Error: {}", str),
+            RuntimeError::LimitExceeded(str) => write!(f, "LimitExceeded Error: {}", str),
+            RuntimeError::ValueOverflow(str) => write!(f, "ValueOverflow Error: {}", str),
+            RuntimeError::CorruptProgram(str) => write!(f, "CorruptProgram Error: {}", str),
+            RuntimeError::Io(err) => write!(f, "Io Error: reading the input or writing the output failed: {}", err),
+            RuntimeError::Cancelled(state) => write!(f, "Cancelled: stopped after {} steps with the pointer at {}", state.steps, state.ptr),
         }
     }
 }
 
+/// Reject a program, that breaks the invariants of `compiler::validate_instructions`,
+/// so a corrupt program fails before it ran instead of in the middle of the run
+pub(crate) fn check_program(program: &[Instruction]) -> Result<(), RuntimeError> {
+    validate_instructions(program).map_err(RuntimeError::from)
+}
+
+impl From<InputError> for RuntimeError {
+    fn from(err: InputError) -> Self {
+        match err {
+            InputError::Script(msg) => RuntimeError::InputScript(msg),
+            InputError::Io(err) => RuntimeError::Io(err),
+        }
+    }
+}
+
+impl From<ValidationError> for RuntimeError {
+    fn from(err: ValidationError) -> Self {
+        RuntimeError::CorruptProgram(err.to_string())
+    }
+}
+
+// checked at compile time, so a change that makes the machine thread bound doesn't go unnoticed
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Machine>();
+    assert_send_sync::<Program>();
+};
+
+/// Steps between two progress events with the `tracing` feature, has to be a multiple of `CANCEL_CHECK_INTERVAL`
+#[cfg(feature = "tracing")]
+const TRACE_EVENT_INTERVAL: u64 = 1 << 16;
+
+/// Result of `Machine::run_report`
+pub struct RunReport {
+    pub result: Result<(), RuntimeError>,
+    pub metrics: Metrics,
+}
+
+/// Steps between two checks of the cancel token and the dump flag, and updates of the live metrics
+const CANCEL_CHECK_INTERVAL: u64 = 1024;
+
+/// Handle to stop a running machine from another thread, see `Machine::cancel_token`
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// ask the machine to stop, it returns `RuntimeError::Cancelled` within a few steps
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Small xorshift64* generator for the rand extension
+/// not suitable for anything but toy programs, but cheap and reproducible
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Rng {
+        // xorshift gets stuck on a zero state
+        Rng { state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed } }
+    }
+
+    pub(crate) fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
+
 /// Machine struct, to emulate a kind of Turingmachine, that can be operated via Brainfuck code
-pub struct Machine {
-    cells: Vec<u8>,
-    ptr: usize,
+/// with the multi-tape extension the machine holds several independent bands, of which one is active
+/// the cells are stored in a `Tape`, by default a dense `VecTape`
+///
+/// Concurrency: a machine owns all of its state and is `Send` and `Sync`, so machines can run on different threads,
+/// while a compiled `Program` is shared between them. The only shared resources are stdin and stdout,
+/// a machine used off the main thread should call `close_stdin` and `capture_output` to not touch them.
+pub struct Machine<T: Tape = VecTape> {
+    tapes: Vec<T>,
+    tape: usize,
+    metrics: Metrics,
+    live_metrics: LiveMetrics,
+    protected: Vec<Range<usize>>,
+    rng: Rng,
+    input: Input,
+    eof: EofPolicy,
+    overflow: OverflowPolicy,
+    pointer: PointerPolicy,
+    dump_flag: Option<Arc<AtomicBool>>,
+    /// collects the output instead of printing it, if set
+    output: Option<Vec<u8>>,
+    cancel: CancelToken,
+    limits: RunLimits,
+    /// when the first instruction ran, for the time limit
+    started: Option<Instant>,
+}
+
+/// Limits for a single run, exceeding one stops the machine with `RuntimeError::LimitExceeded`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct RunLimits {
+    pub max_steps: Option<u64>,
+    pub max_output_bytes: Option<u64>,
+    /// checked every few steps, so the machine may run slightly longer
+    pub timeout: Option<Duration>,
+}
+
+/// What `+` and `-` do, when a cell goes past 255 or below 0
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum OverflowPolicy {
+    /// wrap around to the other end
+    #[default]
+    Wrap,
+    /// stop with an error
+    Error,
+}
+
+/// What `<` and `>` do, when the pointer would leave the tape
+///
+/// ```
+/// use bf_interpreter::prelude::*;
+/// use bf_interpreter::vm::PointerPolicy;
+///
+/// // the optimizer fuses the moves, `<<<` lands exactly on cell 0
+/// let program = Program::from_str(">>>+<<<+", true).unwrap();
+/// let mut machine = MachineBuilder::new().cells(4).build();
+/// assert!(machine.run(&program).is_ok());
+///
+/// let program = Program::from_str(">>><<<<", true).unwrap();
+/// let mut machine = MachineBuilder::new().cells(4).build();
+/// assert!(matches!(machine.run(&program), Err(RuntimeError::CellUnderflow(_))));
+///
+/// let mut machine = MachineBuilder::new().cells(4).pointer(PointerPolicy::Wrap).build();
+/// assert!(machine.run(&program).is_ok());
+/// assert_eq!(machine.ptr(), 3);
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum PointerPolicy {
+    /// stop with `CellUnderflow` left of cell 0 and `CellOverflow` past the last cell
+    #[default]
+    Strict,
+    /// continue at the other end of the tape
+    Wrap,
+}
+
+/// Builder for a `Machine`, for embedders that don't have a `Config`
+/// unset options get the same defaults as on the command line
+pub struct MachineBuilder {
+    cells: usize,
+    tapes: usize,
+    seed: Option<u64>,
+    input: Vec<u8>,
+    prompt: Option<String>,
+    eof: EofPolicy,
+    overflow: OverflowPolicy,
+    pointer: PointerPolicy,
+    limits: RunLimits,
+}
+
+impl Default for MachineBuilder {
+    fn default() -> Self {
+        MachineBuilder {
+            cells: 30000,
+            tapes: 1,
+            seed: None,
+            input: Vec::new(),
+            prompt: None,
+            eof: EofPolicy::Zero,
+            overflow: OverflowPolicy::Wrap,
+            pointer: PointerPolicy::Strict,
+            limits: RunLimits::default(),
+        }
+    }
+}
+
+impl MachineBuilder {
+    pub fn new() -> Self {
+        MachineBuilder::default()
+    }
+
+    /// the options of the command line
+    pub fn from_config(cnfg: &Config) -> Self {
+        MachineBuilder {
+            cells: cnfg.cell_sz,
+            tapes: cnfg.tape_count(),
+            seed: cnfg.seed,
+            input: cnfg.args.clone().map(|prefix| prefix.0).unwrap_or_default(),
+            prompt: cnfg.prompt.clone(),
+            eof: cnfg.eof,
+            overflow: cnfg.overflow_policy(),
+            pointer: cnfg.pointer_bounds,
+            limits: cnfg.run_limits(),
+        }
+    }
+
+    /// amount of cells on every tape
+    pub fn cells(mut self, cells: usize) -> Self {
+        self.cells = cells;
+        self
+    }
+
+    /// amount of tapes, more than one are only useful with the multi-tape extension
+    pub fn tapes(mut self, tapes: usize) -> Self {
+        self.tapes = tapes.max(1);
+        self
+    }
+
+    /// seed for the rand extension, a time based seed is used if not set
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// bytes read by `,` before stdin
+    pub fn input(mut self, input: &[u8]) -> Self {
+        self.input = input.to_vec();
+        self
+    }
+
+    pub fn prompt(mut self, prompt: String) -> Self {
+        self.prompt = Some(prompt);
+        self
+    }
+
+    pub fn eof(mut self, eof: EofPolicy) -> Self {
+        self.eof = eof;
+        self
+    }
+
+    pub fn overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn pointer(mut self, pointer: PointerPolicy) -> Self {
+        self.pointer = pointer;
+        self
+    }
+
+    pub fn limits(mut self, limits: RunLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn build(self) -> Machine {
+        let tapes = (0..self.tapes).map(|_| VecTape::new(self.cells)).collect();
+        self.build_with_tapes(tapes)
+    }
+
+    /// Build a machine on the given tapes instead of dense ones, `cells` and `tapes` are ignored then
+    pub fn build_with_tapes<T: Tape>(self, tapes: Vec<T>) -> Machine<T> {
+        assert!(!tapes.is_empty(), "a machine needs at least one tape");
+        // without an explicit seed every run should produce different numbers
+        let seed = self.seed.unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_nanos() as u64).unwrap_or(0)
+        });
+        let mut input = Input::new(self.input);
+        if let Some(prompt) = self.prompt {
+            input.set_prompt(prompt);
+        }
+        Machine {
+            tapes,
+            tape: 0,
+            metrics: Metrics::default(),
+            live_metrics: LiveMetrics::default(),
+            protected: Vec::new(),
+            rng: Rng::new(seed),
+            input,
+            eof: self.eof,
+            overflow: self.overflow,
+            pointer: self.pointer,
+            dump_flag: None,
+            output: None,
+            cancel: CancelToken::default(),
+            limits: self.limits,
+            started: None,
+        }
+    }
 }
 
 impl Machine {
     /// Create a new Machine from a Config struct
     /// The machine will contain a vec of cells with value 0, and a ptr starting at cell 0
+    /// if the multi-tape extension is enabled, `cnfg.tapes` such bands are created
     pub fn new(cnfg: &Config) -> Machine {
-        let cells = vec![0; cnfg.cell_sz];
-        let ptr = 0;
-        Machine { cells, ptr }
+        MachineBuilder::from_config(cnfg).build()
+    }
+}
+
+impl<T: Tape> Machine<T> {
+    fn band(&self) -> &T {
+        &self.tapes[self.tape]
     }
 
-    pub fn run(&mut self, program: &Program) -> Result<(), RuntimeError> {
+    fn band_mut(&mut self) -> &mut T {
+        &mut self.tapes[self.tape]
+    }
+
+    /// Mark a range of cells as read-only
+    /// any instruction writing to one of these cells (on any tape) will produce a RuntimeError
+    pub fn protect(&mut self, range: Range<usize>) {
+        self.protected.push(range);
+    }
+
+    /// Provide bytes that are read by `,` before anything from stdin
+    pub fn feed_input(&mut self, bytes: &[u8]) {
+        self.input.feed(bytes);
+    }
+
+    /// Read input from a script instead of stdin
+    pub fn set_input_script(&mut self, script: InputScript) {
+        self.input.set_script(script);
+    }
+
+    /// Don't read stdin, the input ends once the fed bytes are used up
+    pub fn close_stdin(&mut self) {
+        self.input.close_stdin();
+    }
+
+    /// Collect the output in memory instead of printing it, it is returned by `take_output`
+    pub fn capture_output(&mut self) {
+        self.output = Some(Vec::new());
+    }
+
+    /// The output collected since the last call, empty if it isn't captured
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.output.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Replace the limits the machine was built with
+    pub fn set_limits(&mut self, limits: RunLimits) {
+        self.limits = limits;
+    }
+
+    /// Handle, that stops the machine when triggered, e.g. from a stop button or a timeout
+    /// the token is checked every few steps, so a cancelled machine stops with a small delay
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// What `+` and `-` do, when a cell goes past 255 or below 0
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow
+    }
+
+    /// Install a flag, that is checked every few steps while running
+    /// whenever it is set (e.g. from a signal handler), the current state is printed to stderr and the flag is cleared
+    pub fn set_dump_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.dump_flag = Some(flag);
+    }
+
+    /// Take a snapshot of the current tape, pointer and step count
+    pub fn snapshot(&self) -> MachineState {
+        MachineState {
+            cells: (0..self.band().len()).map(|index| self.band().get(index)).collect(),
+            ptr: self.band().ptr(),
+            steps: self.metrics.steps,
+        }
+    }
+
+    /// Run until the program ends
+    /// besides a compiled `Program` any instruction slice ending in `Exit` is accepted
+    pub fn run(&mut self, program: &[Instruction]) -> Result<(), RuntimeError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("run", instructions = program.len()).entered();
+
+        check_program(program)?;
         let mut instr_ptr = 0usize;
-        let mut instr = program.first().expect("should always be inside vec");
-
-        while *instr != Instruction::Exit {
-            match instr {
-                Instruction::MvLeft(times) => self.mv_left(*times)?,
-                Instruction::MvRight(times) => self.mv_right(*times)?,
-                Instruction::Inc(times) => self.inc(*times),
-                Instruction::Dec(times) => self.dec(*times),
-                Instruction::Get => self.get(),
-                Instruction::Put => self.put(),
-                Instruction::Jmp(addr) => {
-                    instr_ptr = *addr;
-                    instr = program.get(instr_ptr).expect("jump failed");
-                    continue;
-                },
-                Instruction::JmpZ(addr) => {
-                    if self.value() == 0 {
-                        instr_ptr = *addr + 1;
-                        instr = program.get(instr_ptr).expect("jump failed");
-                        continue;
-                    }
-                },
-                Instruction::Exit => continue,
+        while let Some(next) = self.step(program, instr_ptr)? {
+            instr_ptr = next;
+        }
+        self.finish()
+    }
+
+    /// Like `run`, but also returns the counters of the run
+    pub fn run_report(&mut self, program: &[Instruction]) -> RunReport {
+        let result = self.run(program);
+        self.live_metrics.publish(&self.metrics);
+        RunReport { result, metrics: self.metrics }
+    }
+
+    /// Counters of the machine so far
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+    }
+
+    /// Handle to read the counters from another thread while the machine runs
+    pub fn live_metrics(&self) -> LiveMetrics {
+        self.live_metrics.clone()
+    }
+
+    /// Execute the instruction at `instr_ptr` and return the address of the next one
+    /// `None` means the program reached its end, `finish` should be called then
+    /// an address outside of the program is reported as `RuntimeError::CorruptProgram`
+    pub fn step(&mut self, program: &[Instruction], instr_ptr: usize) -> Result<Option<usize>, RuntimeError> {
+        let Some(instr) = program.get(instr_ptr) else {
+            return Err(RuntimeError::CorruptProgram(format!("Instruction {instr_ptr} is outside of the program ({} instructions)", program.len())));
+        };
+        if *instr == Instruction::Exit {
+            return Ok(None);
+        }
+
+        if self.limits.max_steps.is_some_and(|max| self.metrics.steps >= max) {
+            return Err(RuntimeError::LimitExceeded(format!("Program didn't finish within {} steps", self.metrics.steps)));
+        }
+        if self.metrics.steps.is_multiple_of(CANCEL_CHECK_INTERVAL) {
+            self.live_metrics.publish(&self.metrics);
+            if let Some(timeout) = self.limits.timeout {
+                let started = *self.started.get_or_insert_with(Instant::now);
+                if started.elapsed() > timeout {
+                    return Err(RuntimeError::LimitExceeded(format!("Program didn't finish within {timeout:?}")));
+                }
+            }
+            #[cfg(feature = "tracing")]
+            if self.metrics.steps.is_multiple_of(TRACE_EVENT_INTERVAL) {
+                tracing::trace!(steps = self.metrics.steps, instr_ptr, ptr = self.ptr(), "progress");
+            }
+            if self.cancel.is_cancelled() {
+                return Err(RuntimeError::Cancelled(Box::new(self.snapshot())));
             }
-            instr_ptr += 1;
-            instr = program.get(instr_ptr).expect("should be inside vec");
+            if self.dump_flag.as_ref().is_some_and(|flag| flag.swap(false, Ordering::Relaxed)) {
+                eprintln!("{}", self.state_report(instr_ptr));
+            }
+        }
+        self.metrics.steps += 1;
+        if instr.writes_cell() {
+            self.check_writable(instr, instr_ptr)?;
         }
+        match instr {
+            Instruction::MvLeft(times) => self.mv_left(*times)?,
+            Instruction::MvRight(times) => self.mv_right(*times)?,
+            Instruction::Inc(times) => self.inc(*times)?,
+            Instruction::Dec(times) => self.dec(*times)?,
+            Instruction::Get => self.get()?,
+            Instruction::Put => self.put()?,
+            Instruction::PutStr(bytes) => self.write_output(bytes)?,
+            Instruction::PutN { count, stride } => self.put_n(*count, *stride)?,
+            Instruction::GetN { count, stride } => {
+                for cell in 0..*count {
+                    if cell > 0 {
+                        self.move_ptr(*stride)?;
+                        self.check_writable(instr, instr_ptr)?;
+                    }
+                    self.get()?;
+                }
+            },
+            Instruction::NextTape(times) => self.switch_tape(*times as isize),
+            Instruction::PrevTape(times) => self.switch_tape(-(*times as isize)),
+            Instruction::Rand => {
+                let value = self.rng.next_byte();
+                self.set_value(value);
+            },
+            Instruction::Set(value) => self.set_value(*value),
+            Instruction::MulAdd(updates) => self.mul_add(updates, instr, instr_ptr)?,
+            Instruction::Jmp(addr) => return Ok(Some(*addr)),
+            Instruction::JmpZ(addr) => {
+                if self.value() == 0 {
+                    return Ok(Some(addr.saturating_add(1)));
+                }
+                self.metrics.loop_iterations += 1;
+            },
+            Instruction::If(addr) => {
+                if self.value() == 0 {
+                    return Ok(Some(*addr));
+                }
+                self.metrics.loop_iterations += 1;
+            },
+            Instruction::Exit => {},
+        }
+        Ok(Some(instr_ptr + 1))
+    }
 
-        Ok(())
+    /// Checks that have to pass once the program ended, e.g. that the input script was used up
+    pub fn finish(&mut self) -> Result<(), RuntimeError> {
+        self.input.check_finished().map_err(RuntimeError::InputScript)
     }
 
-    fn value(&self) -> u8 {
-        self.cells[self.ptr]
+    /// Position of the pointer on the active tape
+    pub fn ptr(&self) -> usize {
+        self.band().ptr()
     }
 
-    fn mv_right(&mut self, times: usize) -> Result<(), RuntimeError> {
-        // pointer can't move further than the cell size, so throw a runtime error
-        if self.ptr + times >= self.cells.len() {
+    /// Index of the active tape
+    pub fn tape(&self) -> usize {
+        self.tape
+    }
+
+    /// step count, instruction pointer, data pointer and the cells around the data pointer
+    fn state_report(&self, instr_ptr: usize) -> String {
+        const RADIUS: usize = 8;
+
+        let band = self.band();
+        let start = band.ptr().saturating_sub(RADIUS);
+        let end = (band.ptr() + RADIUS + 1).min(band.len());
+        let mut window = String::new();
+        for index in start..end {
+            let cell = band.get(index);
+            if index == band.ptr() {
+                window.push_str(&format!(">[{cell}]<"));
+            } else {
+                window.push_str(&format!(" [{cell}] "));
+            }
+        }
+        format!("steps: {}, ip: {}, ptr: {}, tape: {}\ncells {}..{}: {}", self.metrics.steps, instr_ptr, band.ptr(), self.tape, start, end, window)
+    }
+
+    fn check_writable(&self, instr: &Instruction, instr_ptr: usize) -> Result<(), RuntimeError> {
+        self.check_cell_writable(instr, instr_ptr, self.band().ptr())
+    }
+
+    fn check_cell_writable(&self, instr: &Instruction, instr_ptr: usize, cell: usize) -> Result<(), RuntimeError> {
+        if self.protected.iter().any(|range| range.contains(&cell)) {
             return Err(
-                RuntimeError::CellOverflow(
-                    format!("Pointer can't move beyond {}. Try running again with a bigger cell size", self.cells.len())
+                RuntimeError::ProtectedWrite(
+                    format!("Instruction {:?} at {} tried to write to protected cell {}", instr, instr_ptr, cell)
                     )
                 );
         }
-        self.ptr += times;
         Ok(())
     }
 
+    /// Value of the current cell
+    pub fn value(&self) -> u8 {
+        let band = self.band();
+        band.get(band.ptr())
+    }
+
+    fn set_value(&mut self, value: u8) {
+        let band = self.band_mut();
+        band.set(band.ptr(), value);
+    }
+
+    fn mv_right(&mut self, times: usize) -> Result<(), RuntimeError> {
+        self.move_ptr(isize::try_from(times).unwrap_or(isize::MAX))
+    }
+
     fn mv_left(&mut self, times: usize) -> Result<(), RuntimeError> {
-        // pointer can't move below 0, so exit program
-        if self.ptr.saturating_sub(times - 1) == 0 {
-            return Err(
-                RuntimeError::CellOverflow(
-                    String::from("Pointer can't move below 0")
-                    )
-                );
+        self.move_ptr(-isize::try_from(times).unwrap_or(isize::MAX))
+    }
+
+    /// move the pointer by `by` cells, any cell from 0 to the last one can be reached
+    /// what happens at the ends of the tape is decided by the `PointerPolicy`
+    fn move_ptr(&mut self, by: isize) -> Result<(), RuntimeError> {
+        let target = self.cell_at(by)?;
+        let band = self.band_mut();
+        let ptr = band.ptr();
+        band.move_by(target as isize - ptr as isize);
+        Ok(())
+    }
+
+    /// index of the cell `by` cells away from the pointer
+    fn cell_at(&self, by: isize) -> Result<usize, RuntimeError> {
+        let band = self.band();
+        let (ptr, len) = (band.ptr(), band.len());
+        match ptr.checked_add_signed(by).filter(|&target| target < len) {
+            Some(target) => Ok(target),
+            None if self.pointer == PointerPolicy::Wrap => Ok((ptr as i128 + by as i128).rem_euclid(len as i128) as usize),
+            None if by < 0 => {
+                Err(RuntimeError::CellUnderflow(String::from("Pointer can't move left of cell 0")))
+            },
+            // pointer can't move further than the cell size, so throw a runtime error
+            None => {
+                Err(RuntimeError::CellOverflow(format!("Pointer can't move beyond {len}. Try running again with a bigger cell size")))
+            },
+        }
+    }
+
+    /// run a whole multiplication loop: the loop would run `count` times, so every target gets `count * factor`
+    fn mul_add(&mut self, updates: &[(isize, u8)], instr: &Instruction, instr_ptr: usize) -> Result<(), RuntimeError> {
+        let count = self.value();
+        if count == 0 {
+            return Ok(());
         }
-        self.ptr -= times;
-        // println!("{}", self.ptr);
+        // the optimizer only fuses loops on wrapping cells, see `Instruction::MulAdd`
+        for &(offset, factor) in updates {
+            let cell = self.cell_at(offset)?;
+            self.check_cell_writable(instr, instr_ptr, cell)?;
+            let band = self.band_mut();
+            band.set(cell, band.get(cell).wrapping_add(count.wrapping_mul(factor)));
+        }
+        self.set_value(0);
+        self.metrics.loop_iterations += count as u64;
+        Ok(())
+    }
+
+    /// switch the active tape, wrapping around at both ends
+    fn switch_tape(&mut self, by: isize) {
+        let count = self.tapes.len() as isize;
+        self.tape = (self.tape as isize + by).rem_euclid(count) as usize;
+    }
+
+    fn inc(&mut self, times: usize) -> Result<(), RuntimeError> {
+        if self.overflow == OverflowPolicy::Error && self.value() as usize + times > u8::MAX as usize {
+            return Err(RuntimeError::ValueOverflow(format!("Cell {} can't go past 255 (value {}, adding {})", self.ptr(), self.value(), times)));
+        }
+        self.set_value(self.value().wrapping_add((times % 256) as u8));
         Ok(())
     }
 
-    fn inc(&mut self, times: usize) {
-        self.cells[self.ptr] = self.cells[self.ptr].wrapping_add((times % u8::MAX as usize) as u8);
+    fn dec(&mut self, times: usize) -> Result<(), RuntimeError> {
+        if self.overflow == OverflowPolicy::Error && (self.value() as usize) < times {
+            return Err(RuntimeError::ValueOverflow(format!("Cell {} can't go below 0 (value {}, subtracting {})", self.ptr(), self.value(), times)));
+        }
+        self.set_value(self.value().wrapping_sub((times % 256) as u8));
+        Ok(())
+    }
+
+    fn put(&mut self) -> Result<(), RuntimeError> {
+        let value = self.value();
+        self.write_output(&[value])
+    }
+
+    /// print `count` cells `stride` apart with a single write and move to the last one
+    /// if a cell is past an end of the tape, the cells before it are still printed, like with single `.`s
+    fn put_n(&mut self, count: usize, stride: isize) -> Result<(), RuntimeError> {
+        let mut bytes = Vec::with_capacity(count);
+        for cell in 0..count as isize {
+            match self.cell_at(cell * stride) {
+                Ok(index) => bytes.push(self.band().get(index)),
+                Err(err) => {
+                    self.write_output(&bytes)?;
+                    return Err(err);
+                },
+            }
+        }
+        self.write_output(&bytes)?;
+        self.move_ptr((count as isize - 1) * stride)
     }
 
-    fn dec(&mut self, times: usize) {
-        self.cells[self.ptr] = self.cells[self.ptr].wrapping_sub((times % u8::MAX as usize) as u8);
+    /// print the bytes with a single write, up to the output limit
+    fn write_output(&mut self, bytes: &[u8]) -> Result<(), RuntimeError> {
+        let allowed = self.limits.max_output_bytes
+            .map_or(bytes.len(), |max| bytes.len().min(max.saturating_sub(self.metrics.output_bytes) as usize));
+        let (written, rest) = bytes.split_at(allowed);
+        self.metrics.output_bytes += written.len() as u64;
+        for &byte in written {
+            self.input.observe_output(byte);
+        }
+        if let Some(output) = &mut self.output {
+            output.extend_from_slice(written);
+        } else if !written.is_empty() {
+            // the bytes are written as they are, a byte above 127 is not turned into a unicode character
+            std::io::stdout().lock().write_all(written).map_err(RuntimeError::Io)?;
+        }
+        if !rest.is_empty() {
+            return Err(RuntimeError::LimitExceeded(format!("Program printed more than {} bytes", self.metrics.output_bytes)));
+        }
+        Ok(())
     }
 
-    fn put(&self) {
-        let ch = char::from(self.value());
-        print!("{ch}");
+    fn get(&mut self) -> Result<(), RuntimeError> {
+        let input = match (self.input.read_byte(self.metrics.steps)?, self.eof) {
+            (Some(byte), _) => {
+                self.metrics.input_bytes += 1;
+                byte
+            },
+            (None, EofPolicy::Zero) => 0,
+            (None, EofPolicy::Max) => u8::MAX,
+            (None, EofPolicy::Unchanged) => return Ok(()),
+        };
+        self.set_value(input);
+        Ok(())
     }
 
-    fn get(&mut self) {
-        let input = std::io::stdin()
-            .bytes()
-            .next()
-            .and_then(|result| result.ok())
-            .unwrap_or(0);
+    /// Dump only the non-zero cells as `index: value` runs
+    /// short zero stretches inside a run are kept, longer ones start a new run
+    /// with multiple tapes every tape is dumped under its own header
+    pub fn dump_nonzero(&self) -> String {
+        if self.tapes.len() == 1 {
+            return tape::dump_nonzero(self.band());
+        }
 
-        self.cells[self.ptr] = input;
+        let mut dump = String::new();
+        for (index, band) in self.tapes.iter().enumerate() {
+            let active = if index == self.tape { " (active)" } else { "" };
+            dump.push_str(&format!("tape {index}{active}:\n{}\n", tape::dump_nonzero(band)));
+        }
+        dump.pop();
+        dump
     }
 }
 
-impl Display for Machine {
+impl<T: Tape> Display for Machine<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let band = self.band();
         let mut cells = String::new();
-        for (index, cell) in self.cells.iter().enumerate() {
-            if index == self.ptr {
+        for index in 0..band.len() {
+            let cell = band.get(index);
+            if index == band.ptr() {
                 cells.push_str(&format!(">[{cell}]<"));
             } else {
                 cells.push_str(&format!(" [{cell}] "));
@@ -133,3 +761,112 @@ impl Display for Machine {
         write!(f, "{}", cells)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{CompileOptions, Program};
+
+    /// run the program without stdin and return the result with the output
+    fn run(source: &str, optimize: bool, input: &[u8]) -> (Result<(), RuntimeError>, Vec<u8>) {
+        let program = Program::from_str(source, optimize).unwrap();
+        let mut machine = MachineBuilder::new().input(input).build();
+        machine.close_stdin();
+        machine.capture_output();
+        let result = machine.run(&program);
+        (result, machine.take_output())
+    }
+
+    /// run the program on a tape of 4 cells and return the result with the final pointer
+    fn run_moves(source: &str, optimize: bool, pointer: PointerPolicy) -> (Result<(), RuntimeError>, usize) {
+        let program = Program::from_str(source, optimize).unwrap();
+        let mut machine = MachineBuilder::new().cells(4).pointer(pointer).build();
+        let result = machine.run(&program);
+        (result, machine.ptr())
+    }
+
+    #[test]
+    fn strict_moves_reach_both_ends() {
+        for optimize in [false, true] {
+            for source in [">>><<<", ">>>+<<<+", "+>>>-<<<"] {
+                let (result, ptr) = run_moves(source, optimize, PointerPolicy::Strict);
+                assert!(result.is_ok(), "{source} (optimize: {optimize})");
+                assert_eq!(ptr, 0, "{source} (optimize: {optimize})");
+            }
+        }
+    }
+
+    #[test]
+    fn strict_moves_fail_past_either_end() {
+        for optimize in [false, true] {
+            for source in ["<", "<<<", ">><<<>", ">>>+<<<<", "+<>"] {
+                let (result, _) = run_moves(source, optimize, PointerPolicy::Strict);
+                assert!(matches!(result, Err(RuntimeError::CellUnderflow(_))), "{source} (optimize: {optimize}): {result:?}");
+                assert_eq!(result.unwrap_err().failure(), Failure::Runtime);
+            }
+            for source in [">>>>", ">>>>>><", "+>>>>-<<<<", ">+>+>+>+", "+>>>><<<<."] {
+                let (result, _) = run_moves(source, optimize, PointerPolicy::Strict);
+                assert!(matches!(result, Err(RuntimeError::CellOverflow(_))), "{source} (optimize: {optimize}): {result:?}");
+                assert_eq!(result.unwrap_err().failure(), Failure::Runtime);
+            }
+        }
+    }
+
+    #[test]
+    fn wrapping_moves_continue_at_the_other_end() {
+        for optimize in [false, true] {
+            for (source, expected) in [("<", 3), ("<<", 2), (">>>>", 0), (">>>>>", 1), ("<+<<<<<", 2), (">>>>>>>><", 3), ("+<>>", 1)] {
+                let (result, ptr) = run_moves(source, optimize, PointerPolicy::Wrap);
+                assert!(result.is_ok(), "{source} (optimize: {optimize})");
+                assert_eq!(ptr, expected, "{source} (optimize: {optimize})");
+            }
+        }
+    }
+
+    #[test]
+    fn machine_runs_on_another_thread() {
+        let program = Program::from_str(",+.,+.", true).unwrap();
+        let mut machine = MachineBuilder::new().input(b"ab").build();
+        machine.close_stdin();
+        machine.capture_output();
+        let handle = std::thread::spawn(move || {
+            let result = machine.run(&program);
+            (result, machine.take_output())
+        });
+        let (result, output) = handle.join().unwrap();
+        assert!(result.is_ok());
+        assert_eq!(output, b"bc");
+    }
+
+    #[test]
+    fn overflow_errors_survive_the_optimizer() {
+        for source in ["-.", "+.>-<.", "-[-]+.", ",[->-<]>.", ".>+[<->-]"] {
+            let mut outputs = Vec::new();
+            for optimize in [false, true] {
+                let options = CompileOptions { optimize, overflow: OverflowPolicy::Error, ..Default::default() };
+                let program = Program::compile(source, &options).unwrap();
+                let mut machine = MachineBuilder::new().overflow(OverflowPolicy::Error).input(b"A").build();
+                machine.close_stdin();
+                machine.capture_output();
+                let result = machine.run(&program);
+                assert!(matches!(result, Err(RuntimeError::ValueOverflow(_))), "{source} (optimize: {optimize}): {result:?}");
+                outputs.push(machine.take_output());
+            }
+            assert_eq!(outputs[0], outputs[1], "{source}");
+        }
+    }
+
+    #[test]
+    fn long_runs_wrap_around_256() {
+        for (times, added, subtracted) in [(255, b'@', b'B'), (256, b'A', b'A'), (257, b'B', b'@')] {
+            for optimize in [false, true] {
+                let (result, output) = run(&format!(",{}.", "+".repeat(times)), optimize, b"A");
+                assert!(result.is_ok());
+                assert_eq!(output, [added], "{times} times + (optimize: {optimize})");
+                let (result, output) = run(&format!(",{}.", "-".repeat(times)), optimize, b"A");
+                assert!(result.is_ok());
+                assert_eq!(output, [subtracted], "{times} times - (optimize: {optimize})");
+            }
+        }
+    }
+}